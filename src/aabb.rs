@@ -12,8 +12,24 @@ impl Aabb {
         Self { box_min, box_max }
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
-        let mut t = t_max;
+    /// An inverted box that any `surrounding_box` with it replaces.
+    pub fn empty() -> Self {
+        Self {
+            box_min: Vec3::splat(f32::INFINITY),
+            box_max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.box_min + self.box_max)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = (self.box_max - self.box_min).max(Vec3::ZERO);
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn hit(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
         for a in 0..3 {
             let inv_d = 1.0 / r.direction[a];
             let mut t0 = (self.box_min[a] - r.origin[a]) * inv_d;
@@ -21,18 +37,31 @@ impl Aabb {
             if inv_d < 0.0 {
                 (t0, t1) = (t1, t0)
             }
-            let t_min = if t0 > t_min { t0 } else { t_min };
-            let t_max = if t1 < t_max { t1 } else { t_max };
+            if t0 > t_min {
+                t_min = t0
+            };
+            if t1 < t_max {
+                t_max = t1
+            };
             if t_max <= t_min {
                 return false;
             }
-            if t_min < t {
-                t = t_min
-            };
         }
         true
     }
 
+    pub fn pad(&self, delta: f32) -> Self {
+        let mut box_min = self.box_min;
+        let mut box_max = self.box_max;
+        for a in 0..3 {
+            if box_max[a] - box_min[a] < delta {
+                box_min[a] -= delta / 2.0;
+                box_max[a] += delta / 2.0;
+            }
+        }
+        Self { box_min, box_max }
+    }
+
     pub fn compare(&self, other: &Self, axis: usize) -> Ordering {
         let x = self.box_min[axis];
         let y = other.box_min[axis];
@@ -1,49 +1,250 @@
-use std::path::PathBuf;
-use ucsd168::edsl::Edsl;
-use ucsd168::geom::point3;
-use ucsd168::io::write_png;
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use ucsd168::display::{Encoding, ToneMap};
+use ucsd168::integrators::gl::GlIntegrator;
+use ucsd168::integrators::path::PathIntegrator;
+use ucsd168::io::{write_film, ExrPrecision};
+use ucsd168::object::Shape;
 use ucsd168::parse::*;
 use ucsd168::render::*;
-use ucsd168::scene::World;
-
-pub fn main() {
-    let world = parse_scene(PathBuf::from(
-        "/Users/jeffreyrosenbluth/Develop/ucsd168/testscenes/scene6.test",
-    ))
-    .unwrap();
-    println!("World loaded");
-    // let world = scene_1();
-    let data = render(&world, gl_integrator);
-    write_png(
-        &data,
-        world.camera.width as u32,
-        world.camera.height as u32,
-        "image",
-    )
+
+/// Renders scene files in the CSE 168 format.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// Scene files to render.
+    #[arg(required = true)]
+    scenes: Vec<PathBuf>,
+
+    /// Output image, or a directory when rendering several images. Defaults
+    /// to the scene's `output` command, else the scene name with `.png`,
+    /// either of them relative to the working directory.
+    /// pfm, hdr and exr files keep the full range of radiance.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Exposure in stops, overriding the scene's `exposure`.
+    #[arg(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+
+    /// Tone map operator: clamp, reinhard, reinhard-extended or aces.
+    #[arg(long)]
+    tonemap: Option<String>,
+
+    /// White point of the reinhard-extended tone map.
+    #[arg(long)]
+    white: Option<f32>,
+
+    /// Transfer function of 8 and 16 bit output, linear or srgb.
+    #[arg(long)]
+    encoding: Option<Encoding>,
+
+    /// Dither 8 and 16 bit output, overriding the scene's `dither`.
+    #[arg(long, overrides_with = "no_dither")]
+    dither: bool,
+
+    /// Don't dither 8 and 16 bit output, overriding the scene's `dither`.
+    #[arg(long, overrides_with = "dither")]
+    no_dither: bool,
+
+    /// Bits per channel of png and ppm output, 8 or 16.
+    #[arg(long, value_parser = parse_bit_depth)]
+    bit_depth: Option<u8>,
+
+    /// Channel type of exr output, half or float.
+    #[arg(long, default_value = "half")]
+    exr: ExrPrecision,
+
+    /// Render only the camera with this name, such as camera2. All cameras
+    /// are rendered by default.
+    #[arg(short, long)]
+    camera: Option<String>,
+
+    /// Image size as WIDTHxHEIGHT, overriding the scene's `size`.
+    #[arg(short, long, value_parser = parse_resolution)]
+    resolution: Option<(u32, u32)>,
+
+    /// Samples per pixel.
+    #[arg(short, long)]
+    samples: Option<u32>,
+
+    /// How radiance is computed.
+    #[arg(short, long, value_enum, default_value_t = IntegratorKind::Gl)]
+    integrator: IntegratorKind,
+
+    /// Worker threads, all cores by default.
+    #[arg(short, long)]
+    threads: Option<usize>,
+
+    /// Seed for the pixel samplers.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Maximum ray depth, overriding the scene's `maxdepth`; negative for no
+    /// limit.
+    #[arg(short = 'd', long)]
+    max_depth: Option<i32>,
+
+    /// Report the scanlines left while rendering.
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum IntegratorKind {
+    /// Whitted ray tracing with the course's OpenGL lighting model.
+    Gl,
+    /// Monte Carlo path tracing.
+    Path,
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32)> {
+    let (w, h) = s
+        .split_once('x')
+        .ok_or_else(|| anyhow!("resolution must be WIDTHxHEIGHT, not {}", s))?;
+    Ok((w.parse()?, h.parse()?))
+}
+
+fn parse_bit_depth(s: &str) -> Result<u8> {
+    match s {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(anyhow!("bit depth must be 8 or 16, not {}", s)),
+    }
 }
 
-pub fn scene_1() -> World {
-    let mut scene = Edsl::default();
-    scene.size(640.0, 480.0);
-    scene.camera(
-        point3(0.0, 0.0, 1.0),
-        point3(0.0, 0.0, 0.0),
-        point3(0.0, 1.0, 0.0),
-        30.0,
+pub fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    let mut failed = 0;
+    for scene in &args.scenes {
+        if let Err(e) = render_scene(&args, scene) {
+            eprintln!("{}: {:#}", scene.display(), e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} scenes failed", failed, args.scenes.len()));
+    }
+    Ok(())
+}
+
+fn render_scene(args: &Args, scene: &Path) -> Result<()> {
+    let start = Instant::now();
+    let mut world = parse_scene(scene.to_path_buf())?;
+    let load_time = start.elapsed();
+
+    for view in &mut world.views {
+        if let Some((width, height)) = args.resolution {
+            view.camera.resize(width as f32, height as f32);
+        }
+    }
+    if let Some(samples) = args.samples {
+        world.samples = samples;
+    }
+    if let Some(seed) = args.seed {
+        world.seed = seed;
+    }
+    if let Some(max_depth) = args.max_depth {
+        world.max_depth = max_depth;
+    }
+    if let Some(exposure) = args.exposure {
+        world.display.exposure = exposure;
+    }
+    if let Some(name) = &args.tonemap {
+        world.display.tone_map = ToneMap::new(name, args.white)?;
+    }
+    if let Some(encoding) = args.encoding {
+        world.display.encoding = encoding;
+    }
+    if args.dither {
+        world.display.dither = true;
+    } else if args.no_dither {
+        world.display.dither = false;
+    }
+    if let Some(bit_depth) = args.bit_depth {
+        world.display.bit_depth = bit_depth;
+    }
+    world.progress = args.verbose;
+
+    let count = |f: fn(&Shape) -> bool| world.objects.0.iter().filter(|s| f(s)).count();
+    let spheres = count(|s| matches!(s, Shape::Sphere(_)));
+    let triangles = count(|s| matches!(s, Shape::Triangle(_)));
+    let discs = count(|s| matches!(s, Shape::Disc(_)));
+    println!("{}", scene.display());
+    println!(
+        "  {} spheres, {} triangles, {} discs, {} lights, {} cameras",
+        spheres,
+        triangles,
+        discs,
+        world.lights.len(),
+        world.views.len()
     );
-    scene.directional(0.0, -1.0, 0.0, 1.0, 1.0, 1.0);
-    scene.point(-4.0, -1.0, -4.0, 12.0, 12.0, 12.0);
-    scene.ambient(0.18, 0.04, 0.18);
-    scene.diffuse(0.9, 0.2, 0.9);
-    scene.specular(1.0, 1.0, 1.0);
-    scene.shininess(200.0);
-    scene.sphere(0.0, -1.0, -4.0, 1.0);
-    scene.push();
-    scene.scale(0.3, 0.15, 0.15);
-    scene.rotate(0.0, 1.0, 1.0, 45.0);
-    scene.translate(0.0, 0.5, -4.0);
-    scene.sphere(0.0, 0.0, 0.0, 1.0);
-    scene.pop();
-    scene.push();
-    scene.run()
+    println!(
+        "  bvh: {} nodes, depth {}",
+        world.bvh.nodes.len(),
+        world.bvh.depth
+    );
+    println!("  loaded in {:.3}s", load_time.as_secs_f64());
+
+    let start = Instant::now();
+    let (views, films) = match &args.camera {
+        Some(name) => {
+            let view = world
+                .view(name)
+                .ok_or_else(|| anyhow!("{} has no camera named {}", scene.display(), name))?;
+            let film = match args.integrator {
+                IntegratorKind::Gl => render_view(&world, view, &mut GlIntegrator),
+                IntegratorKind::Path => render_view(&world, view, &mut PathIntegrator::default()),
+            };
+            (&world.views[view..=view], vec![film])
+        }
+        None => {
+            let films = match args.integrator {
+                IntegratorKind::Gl => render_all(&world, &mut GlIntegrator),
+                IntegratorKind::Path => render_all(&world, &mut PathIntegrator::default()),
+            };
+            (&world.views[..], films)
+        }
+    };
+    let render_time = start.elapsed().as_secs_f64();
+    let samples: u64 = films
+        .iter()
+        .map(|f| f.width as u64 * f.height as u64 * world.samples.max(1) as u64)
+        .sum();
+    println!(
+        "  {} images at {} spp rendered in {:.3}s ({:.0} samples/s)",
+        films.len(),
+        world.samples.max(1),
+        render_time,
+        samples as f64 / render_time
+    );
+
+    let single = args.scenes.len() == 1 && films.len() == 1;
+    if let Some(dir) = args.output.as_ref().filter(|_| !single) {
+        fs::create_dir_all(dir)?;
+    }
+    for (view, film) in views.iter().zip(&films) {
+        let output = match &args.output {
+            Some(output) if single => output.clone(),
+            Some(dir) => dir.join(view.output.file_name().unwrap_or_default()),
+            None => view.output.clone(),
+        };
+        write_film(film, &output, &world.display, args.exr)?;
+        println!(
+            "  wrote {} ({}x{}) from {}",
+            output.display(),
+            film.width,
+            film.height,
+            view.name
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,858 @@
+use crate::geom::{
+    cosine_direction, cross, dot, fresnel, fresnel_conductor, luminance, reflect, refract, vec3,
+    Color, Onb, Vec2, Vec3, BLACK, PI, WHITE,
+};
+use crate::light::Illumination;
+use crate::material::ShadingModel;
+use std::str::FromStr;
+
+/// One way a surface scatters light. Directions are unit vectors pointing
+/// away from the surface, and `n` is the normal to shade with. Reflecting
+/// lobes only look at which side of `n` light arrives from, so integrators
+/// choose whether surfaces are lit from behind by the normal they pass;
+/// dielectrics and coats take `n` to point out of the surface.
+///
+/// Microfacet lobes use GGX with Smith masking and shadowing. Their
+/// `roughness` is the distribution's alpha, the same in every direction
+/// since surfaces have no tangents to orient it by, and below 1e-3 makes the
+/// surface smooth.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lobe {
+    /// Ideal diffuse reflection.
+    Lambertian { albedo: Color },
+    /// The course's highlight, `specular` times the shading model's term
+    /// under each light, scaled by the light's colour. It reflects only
+    /// lights, leaving the rest of the specular reflection to a `Mirror`, so
+    /// it is never sampled.
+    Highlight {
+        specular: Color,
+        shininess: f32,
+        model: ShadingModel,
+    },
+    /// Energy normalized Phong or Blinn-Phong glossy reflection.
+    Phong {
+        specular: Color,
+        exponent: f32,
+        model: ShadingModel,
+    },
+    /// Perfect mirror reflection.
+    Mirror { reflectance: Color },
+    /// Metal, reflecting as much as `fresnel` gives.
+    Conductor { fresnel: Fresnel, roughness: f32 },
+    /// Glass reflecting and refracting by the Fresnel equations, with
+    /// `transmission` of the refracted light let through.
+    Dielectric {
+        ior: f32,
+        transmission: Color,
+        roughness: f32,
+    },
+    /// `base` under a smooth clear coat with index of refraction `ior`.
+    Coated { ior: f32, base: Vec<Lobe> },
+}
+
+/// Fraction of light a conductor reflects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fresnel {
+    /// Schlick's approximation from the reflectance at normal incidence.
+    Schlick(Color),
+    /// A metal with complex index of refraction `eta + i k`.
+    Conductor { eta: Color, k: Color },
+}
+
+impl Fresnel {
+    pub fn reflectance(&self, cos: f32) -> Color {
+        let cos = cos.clamp(0.0, 1.0);
+        match *self {
+            Fresnel::Schlick(f0) => f0 + (WHITE - f0) * (1.0 - cos).powi(5),
+            Fresnel::Conductor { eta, k } => Color::new(
+                fresnel_conductor(cos, eta.x, k.x),
+                fresnel_conductor(cos, eta.y, k.y),
+                fresnel_conductor(cos, eta.z, k.z),
+            ),
+        }
+    }
+}
+
+/// Measured metals, at 650, 550 and 450 nm for red, green and blue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metal {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Metal {
+    pub fn fresnel(self) -> Fresnel {
+        let (eta, k) = match self {
+            Metal::Gold => ((0.143, 0.374, 1.442), (3.983, 2.385, 1.603)),
+            Metal::Copper => ((0.200, 0.924, 1.102), (3.912, 2.452, 2.142)),
+            Metal::Aluminium => ((1.657, 0.880, 0.521), (9.224, 6.270, 4.837)),
+            Metal::Silver => ((0.155, 0.117, 0.138), (4.828, 3.122, 2.147)),
+        };
+        Fresnel::Conductor {
+            eta: Color::new(eta.0, eta.1, eta.2),
+            k: Color::new(k.0, k.1, k.2),
+        }
+    }
+}
+
+impl FromStr for Metal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gold" => Ok(Metal::Gold),
+            "copper" => Ok(Metal::Copper),
+            "aluminium" | "aluminum" => Ok(Metal::Aluminium),
+            "silver" => Ok(Metal::Silver),
+            _ => Err(anyhow::anyhow!("unknown metal {}", s)),
+        }
+    }
+}
+
+/// A direction picked by `Bsdf::sample`.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// BSDF times cosine over density, the factor a path's throughput takes.
+    pub weight: Color,
+    /// Density in solid angle, or the probability of a delta lobe's
+    /// direction.
+    pub pdf: f32,
+    /// Whether the direction came from a delta lobe.
+    pub delta: bool,
+}
+
+/// The lobes of a surface, summed.
+#[derive(Debug, Clone)]
+pub struct Bsdf<'a> {
+    lobes: Lobes<'a>,
+}
+
+/// Lobes borrowed from a material, or a few built for one hit and held
+/// inline so that building them does not allocate.
+#[derive(Debug, Clone)]
+enum Lobes<'a> {
+    Borrowed(&'a [Lobe]),
+    Inline { lobes: [Lobe; 3], len: usize },
+}
+
+/// Fills the unused slots of inline lobes.
+const UNUSED: Lobe = Lobe::Lambertian { albedo: BLACK };
+
+impl<'a> Bsdf<'a> {
+    pub fn new(lobes: &'a [Lobe]) -> Self {
+        Self {
+            lobes: Lobes::Borrowed(lobes),
+        }
+    }
+
+    /// The lobes that are present, in order, held inline.
+    pub fn inline(lobes: [Option<Lobe>; 3]) -> Self {
+        let mut inline = [UNUSED; 3];
+        let mut len = 0;
+        for lobe in lobes.into_iter().flatten() {
+            inline[len] = lobe;
+            len += 1;
+        }
+        Self {
+            lobes: Lobes::Inline { lobes: inline, len },
+        }
+    }
+
+    pub fn lobes(&self) -> &[Lobe] {
+        match &self.lobes {
+            Lobes::Borrowed(lobes) => lobes,
+            Lobes::Inline { lobes, len } => &lobes[..*len],
+        }
+    }
+
+    pub fn evaluate(&self, n: Vec3, wo: Vec3, wi: Vec3) -> Color {
+        evaluate(self.lobes(), n, wo, wi)
+    }
+
+    pub fn pdf(&self, n: Vec3, wo: Vec3, wi: Vec3) -> f32 {
+        pdf(self.lobes(), n, wo, wi)
+    }
+
+    /// A direction from one lobe, picked by `u_lobe` in proportion to the
+    /// lobes' weights, with `u` picking the direction.
+    pub fn sample(&self, n: Vec3, wo: Vec3, u_lobe: f32, u: Vec2) -> Option<BsdfSample> {
+        sample(self.lobes(), n, wo, u_lobe, u)
+    }
+
+    /// Light reflected towards `wo` from `light`, in its units.
+    pub fn direct(&self, n: Vec3, wo: Vec3, light: &Illumination) -> Color {
+        let mut color = BLACK;
+        for lobe in self.lobes() {
+            color += lobe.direct(n, wo, light);
+        }
+        color
+    }
+
+    /// Directions the delta lobes scatter `wo` into and their weights.
+    pub fn delta_directions(&self, n: Vec3, wo: Vec3) -> Vec<(Vec3, Color)> {
+        let mut directions = Vec::new();
+        for lobe in self.lobes() {
+            lobe.delta_directions(n, wo, WHITE, &mut directions);
+        }
+        directions
+    }
+
+    /// Whether light passes through, so that `n` must point out of the
+    /// surface for the inside to be known.
+    pub fn transmits(&self) -> bool {
+        self.lobes().iter().any(|lobe| lobe.transmits())
+    }
+}
+
+fn evaluate(lobes: &[Lobe], n: Vec3, wo: Vec3, wi: Vec3) -> Color {
+    let mut f = BLACK;
+    for lobe in lobes {
+        f += lobe.evaluate(n, wo, wi);
+    }
+    f
+}
+
+fn pdf(lobes: &[Lobe], n: Vec3, wo: Vec3, wi: Vec3) -> f32 {
+    let total: f32 = lobes.iter().map(|lobe| lobe.weight()).sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    lobes
+        .iter()
+        .map(|lobe| lobe.weight() / total * lobe.pdf(n, wo, wi))
+        .sum()
+}
+
+fn sample(lobes: &[Lobe], n: Vec3, wo: Vec3, u_lobe: f32, u: Vec2) -> Option<BsdfSample> {
+    let total: f32 = lobes.iter().map(|lobe| lobe.weight()).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let target = u_lobe * total;
+    let mut start = 0.0;
+    let mut chosen = None;
+    for lobe in lobes {
+        let weight = lobe.weight();
+        if weight > 0.0 {
+            chosen = Some((lobe, start, weight));
+            if target < start + weight {
+                break;
+            }
+        }
+        start += weight;
+    }
+    let (lobe, start, weight) = chosen?;
+    // Reuse what is left of u_lobe within the chosen lobe's share.
+    let u_choice = ((target - start) / weight).clamp(0.0, 1.0 - f32::EPSILON);
+    let p = weight / total;
+    let mut s = lobe.sample(n, wo, u_choice, u)?;
+    if s.delta || p >= 1.0 {
+        s.weight /= p;
+        s.pdf *= p;
+        return Some(s);
+    }
+    // The other lobes could have picked the same direction.
+    s.pdf = pdf(lobes, n, wo, s.wi);
+    let f = lobes
+        .iter()
+        .filter(|lobe| lobe.weight() > 0.0)
+        .fold(BLACK, |f, lobe| f + lobe.evaluate(n, wo, s.wi));
+    s.weight = f * dot(n, s.wi).abs() / s.pdf;
+    Some(s)
+}
+
+impl Lobe {
+    /// Relative chance of sampling this lobe.
+    pub fn weight(&self) -> f32 {
+        match self {
+            Lobe::Lambertian { albedo } => luminance(*albedo),
+            Lobe::Highlight { .. } => 0.0,
+            Lobe::Phong { specular, .. } => luminance(*specular),
+            Lobe::Mirror { reflectance } => luminance(*reflectance),
+            Lobe::Conductor { fresnel, .. } => luminance(fresnel.reflectance(1.0)),
+            Lobe::Dielectric { .. } | Lobe::Coated { .. } => 1.0,
+        }
+    }
+
+    pub fn is_delta(&self) -> bool {
+        match self {
+            Lobe::Mirror { .. } => true,
+            Lobe::Conductor { roughness, .. } | Lobe::Dielectric { roughness, .. } => {
+                is_smooth(*roughness)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn transmits(&self) -> bool {
+        match self {
+            Lobe::Dielectric { .. } => true,
+            Lobe::Coated { base, .. } => base.iter().any(|lobe| lobe.transmits()),
+            _ => false,
+        }
+    }
+
+    /// The BSDF, zero for delta lobes.
+    pub fn evaluate(&self, n: Vec3, wo: Vec3, wi: Vec3) -> Color {
+        if self.is_delta() {
+            return BLACK;
+        }
+        if let &Lobe::Dielectric {
+            ior,
+            transmission,
+            roughness,
+        } = self
+        {
+            return rough_dielectric(ior, transmission, roughness, n, wo, wi).0;
+        }
+        let cos_i = dot(n, wi);
+        match self {
+            _ if cos_i <= 0.0 => BLACK,
+            Lobe::Lambertian { albedo } => *albedo / PI,
+            &Lobe::Highlight {
+                specular,
+                shininess,
+                model,
+            } => specular * model.highlight(n, wi, wo, shininess) / (PI * cos_i),
+            &Lobe::Phong {
+                specular,
+                exponent,
+                model,
+            } => match model {
+                ShadingModel::Phong => {
+                    let cos_alpha = dot(reflect(-wo, n), wi).max(0.0);
+                    specular * (exponent + 2.0) / (2.0 * PI) * cos_alpha.powf(exponent)
+                }
+                ShadingModel::BlinnPhong => {
+                    // The exact normalization, as the usual (n + 8) / 8 PI
+                    // reflects more than arrives.
+                    let norm = (exponent + 2.0) * (exponent + 4.0)
+                        / (8.0 * PI * (2f32.powf(-exponent / 2.0) + exponent));
+                    let cos_h = dot(n, (wi + wo).normalize()).max(0.0);
+                    specular * norm * cos_h.powf(exponent)
+                }
+            },
+            &Lobe::Conductor { fresnel, roughness } => {
+                let frame = Onb::build_from_w(n);
+                let (wo, wi) = (frame.project(wo), frame.project(wi));
+                if wo.z <= 0.0 {
+                    return BLACK;
+                }
+                let h = (wo + wi).normalize();
+                let alpha = alpha(roughness);
+                fresnel.reflectance(dot(wo, h)) * ggx(h, alpha) * smith_g(wo, wi, alpha)
+                    / (4.0 * wi.z * wo.z)
+            }
+            Lobe::Coated { ior, base } => {
+                let cos_o = dot(n, wo).max(0.0);
+                let through = (1.0 - fresnel(cos_i, 1.0 / ior)) * (1.0 - fresnel(cos_o, 1.0 / ior));
+                through * evaluate(base, n, wo, wi)
+            }
+            Lobe::Mirror { .. } | Lobe::Dielectric { .. } => BLACK,
+        }
+    }
+
+    /// Density of `sample` picking `wi`, zero for delta lobes.
+    pub fn pdf(&self, n: Vec3, wo: Vec3, wi: Vec3) -> f32 {
+        if self.is_delta() {
+            return 0.0;
+        }
+        if let &Lobe::Dielectric {
+            ior,
+            transmission,
+            roughness,
+        } = self
+        {
+            return rough_dielectric(ior, transmission, roughness, n, wo, wi).1;
+        }
+        let cos_i = dot(n, wi);
+        match self {
+            Lobe::Highlight { .. } => 0.0,
+            _ if cos_i <= 0.0 => 0.0,
+            Lobe::Lambertian { .. } => cos_i / PI,
+            &Lobe::Phong {
+                exponent, model, ..
+            } => match model {
+                ShadingModel::Phong => {
+                    let cos_alpha = dot(reflect(-wo, n), wi).max(0.0);
+                    (exponent + 1.0) / (2.0 * PI) * cos_alpha.powf(exponent)
+                }
+                ShadingModel::BlinnPhong => {
+                    let h = (wi + wo).normalize();
+                    let cos_h = dot(n, h).max(0.0);
+                    (exponent + 1.0) / (2.0 * PI) * cos_h.powf(exponent)
+                        / (4.0 * dot(wo, h).abs().max(1e-6))
+                }
+            },
+            &Lobe::Conductor { roughness, .. } => {
+                let frame = Onb::build_from_w(n);
+                let (wo, wi) = (frame.project(wo), frame.project(wi));
+                if wo.z <= 0.0 {
+                    return 0.0;
+                }
+                let h = (wo + wi).normalize();
+                visible_normal_pdf(wo, h, alpha(roughness)) / (4.0 * dot(wo, h).max(1e-6))
+            }
+            Lobe::Coated { ior, base } => {
+                let coat = fresnel(dot(n, wo).max(0.0), 1.0 / ior);
+                (1.0 - coat) * pdf(base, n, wo, wi)
+            }
+            Lobe::Mirror { .. } | Lobe::Dielectric { .. } => 0.0,
+        }
+    }
+
+    /// A direction scattered from `wo`. `u_choice` picks between reflection
+    /// and refraction, or coat and base, and `u` picks the direction.
+    pub fn sample(&self, n: Vec3, wo: Vec3, u_choice: f32, u: Vec2) -> Option<BsdfSample> {
+        let sample = match self {
+            Lobe::Lambertian { albedo } => {
+                let wi = Onb::build_from_w(n).local(cosine_direction(u.x, u.y));
+                BsdfSample {
+                    wi,
+                    weight: *albedo,
+                    pdf: dot(n, wi).max(0.0) / PI,
+                    delta: false,
+                }
+            }
+            Lobe::Highlight { .. } => return None,
+            &Lobe::Phong {
+                exponent, model, ..
+            } => {
+                let cos_theta = u.x.powf(1.0 / (exponent + 1.0));
+                let local = cone_local(cos_theta, u.y);
+                let wi = match model {
+                    ShadingModel::Phong => Onb::build_from_w(reflect(-wo, n)).local(local),
+                    ShadingModel::BlinnPhong => reflect(-wo, Onb::build_from_w(n).local(local)),
+                };
+                self.continuous(n, wo, wi)?
+            }
+            Lobe::Mirror { reflectance } => BsdfSample {
+                wi: reflect(-wo, n),
+                weight: *reflectance,
+                pdf: 1.0,
+                delta: true,
+            },
+            &Lobe::Conductor { fresnel, roughness } if is_smooth(roughness) => BsdfSample {
+                wi: reflect(-wo, n),
+                weight: fresnel.reflectance(dot(n, wo)),
+                pdf: 1.0,
+                delta: true,
+            },
+            &Lobe::Conductor { roughness, .. } => {
+                let frame = Onb::build_from_w(n);
+                let wo_local = frame.project(wo);
+                if wo_local.z <= 0.0 {
+                    return None;
+                }
+                let h = sample_visible_normal(wo_local, alpha(roughness), u);
+                self.continuous(n, wo, frame.local(reflect(-wo_local, h)))?
+            }
+            &Lobe::Dielectric {
+                ior, transmission, ..
+            } if self.is_delta() => {
+                let (reflectance, reflected, refracted) = refraction(ior, n, wo);
+                match refracted {
+                    Some(refracted) if u_choice >= reflectance => BsdfSample {
+                        wi: refracted,
+                        weight: transmission,
+                        pdf: 1.0 - reflectance,
+                        delta: true,
+                    },
+                    _ => BsdfSample {
+                        wi: reflected,
+                        weight: WHITE,
+                        pdf: reflectance,
+                        delta: true,
+                    },
+                }
+            }
+            &Lobe::Dielectric { ior, roughness, .. } => {
+                let frame = Onb::build_from_w(n);
+                let wo_local = frame.project(wo);
+                let h = sample_visible_normal(wo_local, alpha(roughness), u);
+                let cos = dot(wo_local, h);
+                let (h, eta_ratio) = if cos > 0.0 { (h, 1.0 / ior) } else { (-h, ior) };
+                let reflectance = fresnel(cos.abs(), eta_ratio);
+                let reflected = u_choice < reflectance;
+                let wi = if reflected {
+                    reflect(-wo_local, h)
+                } else {
+                    refract(-wo_local, h, eta_ratio)
+                };
+                // Off a steep microfacet the direction can end up on the
+                // wrong side of the surface.
+                if (wi.z * wo_local.z > 0.0) != reflected {
+                    return None;
+                }
+                self.continuous(n, wo, frame.local(wi))?
+            }
+            Lobe::Coated { ior, base } => {
+                let coat = fresnel(dot(n, wo).max(0.0), 1.0 / ior);
+                if u_choice < coat {
+                    BsdfSample {
+                        wi: reflect(-wo, n),
+                        weight: WHITE,
+                        pdf: coat,
+                        delta: true,
+                    }
+                } else {
+                    let u_base = (u_choice - coat) / (1.0 - coat);
+                    let mut s = sample(base, n, wo, u_base, u)?;
+                    let through = 1.0 - fresnel(dot(n, s.wi).max(0.0), 1.0 / ior);
+                    // The coat lets through 1 - coat on the way in, which
+                    // cancels against picking the base.
+                    s.weight *= through;
+                    s.pdf *= 1.0 - coat;
+                    s
+                }
+            }
+        };
+        Some(sample)
+    }
+
+    /// Sample of a non-delta lobe in direction `wi`.
+    fn continuous(&self, n: Vec3, wo: Vec3, wi: Vec3) -> Option<BsdfSample> {
+        let cos_i = dot(n, wi).abs();
+        let pdf = self.pdf(n, wo, wi);
+        if cos_i <= 0.0 || pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.evaluate(n, wo, wi) * cos_i / pdf,
+            pdf,
+            delta: false,
+        })
+    }
+
+    /// Light reflected towards `wo` from `light`, in its units.
+    pub fn direct(&self, n: Vec3, wo: Vec3, light: &Illumination) -> Color {
+        let wi = light.direction;
+        match self {
+            Lobe::Lambertian { albedo } => light.color * *albedo * dot(n, wi).max(0.0),
+            &Lobe::Highlight {
+                specular,
+                shininess,
+                model,
+            } => specular * model.highlight(n, wi, wo, shininess) * light.highlight,
+            _ if self.is_delta() => BLACK,
+            // Only rough glass lets light through from the far side.
+            _ => PI * self.evaluate(n, wo, wi) * light.color * dot(n, wi).abs(),
+        }
+    }
+
+    /// Adds the directions the delta parts of this lobe scatter `wo` into,
+    /// with their weights times `scale`.
+    fn delta_directions(&self, n: Vec3, wo: Vec3, scale: Color, out: &mut Vec<(Vec3, Color)>) {
+        if !self.is_delta() && !matches!(self, Lobe::Coated { .. }) {
+            return;
+        }
+        match self {
+            Lobe::Mirror { reflectance } => out.push((reflect(-wo, n), scale * *reflectance)),
+            Lobe::Conductor { fresnel, .. } => {
+                out.push((reflect(-wo, n), scale * fresnel.reflectance(dot(n, wo))))
+            }
+            &Lobe::Dielectric {
+                ior, transmission, ..
+            } => {
+                let (reflectance, reflected, refracted) = refraction(ior, n, wo);
+                out.push((reflected, scale * reflectance));
+                if let Some(refracted) = refracted {
+                    out.push((refracted, scale * (1.0 - reflectance) * transmission));
+                }
+            }
+            Lobe::Coated { ior, base } => {
+                let coat = fresnel(dot(n, wo).max(0.0), 1.0 / ior);
+                out.push((reflect(-wo, n), scale * coat));
+                // Only a mirror under the coat has its own delta direction,
+                // the coat's, which is let through twice.
+                let through = (1.0 - coat) * (1.0 - coat);
+                for lobe in base {
+                    lobe.delta_directions(n, wo, scale * through, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Roughness below which a microfacet lobe is a smooth surface.
+const MIN_ALPHA: f32 = 1e-3;
+
+fn is_smooth(roughness: f32) -> bool {
+    roughness < MIN_ALPHA
+}
+
+/// The GGX alpha of `roughness`, kept off zero where the distribution
+/// degenerates.
+fn alpha(roughness: f32) -> Vec2 {
+    Vec2::splat(roughness.max(MIN_ALPHA))
+}
+
+/// Fresnel reflectance of a smooth dielectric with index of refraction
+/// `ior` seen from `wo`, with `n` pointing out of it, and the reflected
+/// direction and the refracted one unless all light is reflected.
+fn refraction(ior: f32, n: Vec3, wo: Vec3) -> (f32, Vec3, Option<Vec3>) {
+    let (n, eta_ratio) = if dot(wo, n) > 0.0 {
+        (n, 1.0 / ior)
+    } else {
+        (-n, ior)
+    };
+    let reflectance = fresnel(dot(wo, n), eta_ratio);
+    let reflected = reflect(-wo, n);
+    let refracted = (reflectance < 1.0).then(|| refract(-wo, n, eta_ratio));
+    (reflectance, reflected, refracted)
+}
+
+/// BSDF and density of sampling `wi` of rough glass, after Walter et al.'s
+/// microfacet model of refraction, with `n` pointing out of it.
+fn rough_dielectric(
+    ior: f32,
+    transmission: Color,
+    roughness: f32,
+    n: Vec3,
+    wo: Vec3,
+    wi: Vec3,
+) -> (Color, f32) {
+    let frame = Onb::build_from_w(n);
+    let (wo, wi) = (frame.project(wo), frame.project(wi));
+    if wo.z == 0.0 || wi.z == 0.0 {
+        return (BLACK, 0.0);
+    }
+    let reflected = wo.z * wi.z > 0.0;
+    // Index of refraction on the side of wi over that on the side of wo.
+    let eta = match (reflected, wo.z > 0.0) {
+        (true, _) => 1.0,
+        (false, true) => ior,
+        (false, false) => 1.0 / ior,
+    };
+    let h = wo + eta * wi;
+    if h.length_squared() == 0.0 {
+        return (BLACK, 0.0);
+    }
+    let h = if h.z < 0.0 {
+        -h.normalize()
+    } else {
+        h.normalize()
+    };
+    // Microfacets facing away from either direction cannot scatter between them.
+    if dot(h, wo) * wo.z <= 0.0 || dot(h, wi) * wi.z <= 0.0 {
+        return (BLACK, 0.0);
+    }
+    let cos_o = dot(wo, h);
+    let reflectance = if cos_o > 0.0 {
+        fresnel(cos_o, 1.0 / ior)
+    } else {
+        fresnel(-cos_o, ior)
+    };
+    let alpha = alpha(roughness);
+    let d = ggx(h, alpha);
+    let g = smith_g(wo, wi, alpha);
+    let visible = visible_normal_pdf(wo, h, alpha);
+    if reflected {
+        let f = d * g * reflectance / (4.0 * wi.z.abs() * wo.z.abs());
+        let pdf = visible / (4.0 * cos_o.abs()) * reflectance;
+        (f * WHITE, pdf)
+    } else {
+        let denom = dot(wi, h) + cos_o / eta;
+        let denom = denom * denom;
+        let f = d * g * (1.0 - reflectance) * (dot(wi, h) * cos_o / (wi.z * wo.z * denom)).abs();
+        let pdf = visible * dot(wi, h).abs() / denom * (1.0 - reflectance);
+        (f * transmission, pdf)
+    }
+}
+
+/// Unit vector `cos_theta` from +z at the angle `2 PI u` around it.
+fn cone_local(cos_theta: f32, u: f32) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u;
+    vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// The microfacet functions below work in the surface's frame, with the
+// normal along +z, and take their alpha from `alpha`.
+
+/// GGX distribution of microfacet normals `h`.
+fn ggx(h: Vec3, alpha: Vec2) -> f32 {
+    if h.z <= 0.0 {
+        return 0.0;
+    }
+    let e = (h.x / alpha.x).powi(2) + (h.y / alpha.y).powi(2) + h.z * h.z;
+    1.0 / (PI * alpha.x * alpha.y * e * e)
+}
+
+/// Smith's Λ, the area of microfacets hidden from `v` relative to those
+/// seen.
+fn smith_lambda(v: Vec3, alpha: Vec2) -> f32 {
+    let tan2 = ((alpha.x * v.x).powi(2) + (alpha.y * v.y).powi(2)) / (v.z * v.z);
+    if !tan2.is_finite() {
+        return f32::INFINITY;
+    }
+    0.5 * ((1.0 + tan2).sqrt() - 1.0)
+}
+
+/// Fraction of microfacets seen from `v`.
+fn smith_g1(v: Vec3, alpha: Vec2) -> f32 {
+    1.0 / (1.0 + smith_lambda(v, alpha))
+}
+
+/// Fraction of microfacets seen from both `wo` and `wi`, higher ones being
+/// more likely to be seen from both.
+fn smith_g(wo: Vec3, wi: Vec3, alpha: Vec2) -> f32 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// Density of `sample_visible_normal` picking `h`.
+fn visible_normal_pdf(wo: Vec3, h: Vec3, alpha: Vec2) -> f32 {
+    smith_g1(wo, alpha) * dot(wo, h).abs() * ggx(h, alpha) / wo.z.abs()
+}
+
+/// A microfacet normal in proportion to how much of it `wo` sees, by
+/// Heitz's sampling of visible normals: the microfacets are stretched into a
+/// hemisphere, whose projection seen from `wo` is sampled.
+fn sample_visible_normal(wo: Vec3, alpha: Vec2, u: Vec2) -> Vec3 {
+    let v = vec3(alpha.x * wo.x, alpha.y * wo.y, wo.z).normalize();
+    let v = if v.z < 0.0 { -v } else { v };
+    let t1 = if v.z < 0.99999 {
+        cross(vec3(0.0, 0.0, 1.0), v).normalize()
+    } else {
+        vec3(1.0, 0.0, 0.0)
+    };
+    let t2 = cross(v, t1);
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    let p1 = r * phi.cos();
+    // Squash the disc into the part of the hemisphere's outline v sees.
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    let h = p1 * t1 + p2 * t2 + p3 * v;
+    vec3(alpha.x * h.x, alpha.y * h.y, h.z.max(1e-6)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::{random_unit_vector, vec2};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    const N: Vec3 = Vec3::Z;
+
+    /// A lobe of every kind that can be sampled, smooth and rough.
+    fn lobes() -> Vec<Lobe> {
+        let rough = 0.3;
+        let lambertian = Lobe::Lambertian {
+            albedo: Color::new(0.8, 0.5, 0.2),
+        };
+        vec![
+            lambertian.clone(),
+            Lobe::Phong {
+                specular: WHITE,
+                exponent: 20.0,
+                model: ShadingModel::Phong,
+            },
+            Lobe::Phong {
+                specular: WHITE,
+                exponent: 20.0,
+                model: ShadingModel::BlinnPhong,
+            },
+            Lobe::Mirror { reflectance: WHITE },
+            Lobe::Conductor {
+                fresnel: Metal::Gold.fresnel(),
+                roughness: 0.0,
+            },
+            Lobe::Conductor {
+                fresnel: Fresnel::Schlick(WHITE),
+                roughness: rough,
+            },
+            Lobe::Dielectric {
+                ior: 1.5,
+                transmission: WHITE,
+                roughness: 0.0,
+            },
+            Lobe::Dielectric {
+                ior: 1.5,
+                transmission: WHITE,
+                roughness: rough,
+            },
+            Lobe::Coated {
+                ior: 1.5,
+                base: vec![lambertian],
+            },
+        ]
+    }
+
+    /// A direction on the side of `N` that lobes which only reflect see.
+    fn outgoing(rng: &mut SmallRng) -> Vec3 {
+        let v = random_unit_vector(rng);
+        vec3(v.x, v.y, v.z.abs().max(0.05)).normalize()
+    }
+
+    fn sample(lobes: &[Lobe], wo: Vec3, rng: &mut SmallRng) -> Option<BsdfSample> {
+        let u = vec2(rng.gen(), rng.gen());
+        Bsdf::new(lobes).sample(N, wo, rng.gen(), u)
+    }
+
+    #[test]
+    fn sampled_densities_match_pdf() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut mixtures: Vec<Vec<Lobe>> = lobes().into_iter().map(|l| vec![l]).collect();
+        mixtures.push(lobes()[..3].to_vec());
+        for lobes in &mixtures {
+            let bsdf = Bsdf::new(lobes);
+            for _ in 0..1000 {
+                let wo = outgoing(&mut rng);
+                let s = match sample(lobes, wo, &mut rng) {
+                    Some(s) if !s.delta => s,
+                    _ => continue,
+                };
+                let pdf = bsdf.pdf(N, wo, s.wi);
+                assert!(
+                    (s.pdf - pdf).abs() <= 1e-3 * pdf,
+                    "{:?}: sampled {} but pdf {}",
+                    lobes,
+                    s.pdf,
+                    pdf
+                );
+                let weight = bsdf.evaluate(N, wo, s.wi) * dot(N, s.wi).abs() / pdf;
+                assert!(
+                    (s.weight - weight).abs().max_element() <= 1e-3 * weight.max_element(),
+                    "{:?}: sampled weight {} but {}",
+                    lobes,
+                    s.weight,
+                    weight
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lobes_reflect_at_most_what_arrives() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        for lobe in lobes() {
+            let lobes = [lobe];
+            for _ in 0..20 {
+                let wo = outgoing(&mut rng);
+                let n = 20000;
+                let mut albedo = BLACK;
+                for _ in 0..n {
+                    if let Some(s) = sample(&lobes, wo, &mut rng) {
+                        albedo += s.weight;
+                    }
+                }
+                albedo /= n as f32;
+                assert!(
+                    albedo.max_element() <= 1.02,
+                    "{:?} reflects {} towards {}",
+                    lobes[0],
+                    albedo,
+                    wo
+                );
+            }
+        }
+    }
+}
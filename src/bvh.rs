@@ -3,6 +3,43 @@ use crate::geom::{Ray, Vec3};
 use crate::object::{Hit, Objects};
 use std::cmp::Ordering;
 
+const STACK_SIZE: usize = 64;
+
+const SAH_BINS: usize = 12;
+const SAH_MAX_LEAF: usize = 4;
+const SAH_TRAVERSAL_COST: f32 = 1.0;
+const SAH_PARALLEL_THRESHOLD: usize = 1024;
+
+/// How the BVH over a scene's shapes is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Builder {
+    /// Split on the mean of the box minimums, round robin over the axes.
+    #[default]
+    Mean,
+    /// Binned surface area heuristic with multi-shape leaves, built in parallel.
+    Sah,
+}
+
+impl std::str::FromStr for Builder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Builder::Mean),
+            "sah" => Ok(Builder::Sah),
+            _ => Err(anyhow::anyhow!("unknown bvh builder {}", s)),
+        }
+    }
+}
+
+pub fn build(objects: &Objects, builder: Builder) -> Node {
+    let indices: Vec<usize> = (0..objects.len()).collect();
+    match builder {
+        Builder::Mean => Node::new(objects, indices, 0),
+        Builder::Sah => Node::sah(objects, indices),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Node {
     Branch {
@@ -14,6 +51,10 @@ pub enum Node {
         shape_index: usize,
         bbox: Aabb,
     },
+    Group {
+        shape_indices: Vec<usize>,
+        bbox: Aabb,
+    },
     Empty,
 }
 
@@ -29,13 +70,17 @@ impl Node {
                 right: _,
                 bbox,
             } => *bbox,
+            Node::Group {
+                shape_indices: _,
+                bbox,
+            } => *bbox,
             Node::Empty => Aabb::new(Vec3::ZERO, Vec3::ZERO),
         }
     }
 
     pub fn new(objects: &Objects, indices: Vec<usize>, axis: usize) -> Self {
         let object_span = indices.len();
-        let node = match object_span {
+        match object_span {
             0 => Node::Empty,
             1 => {
                 let i = indices[0];
@@ -51,18 +96,18 @@ impl Node {
                 let i1 = indices[1];
                 let first_bbox = objects[i0].bounding_box();
                 let second_bbox = objects[i1].bounding_box();
-                let (f, s) = match first_bbox.compare(&second_bbox, axis) {
-                    Ordering::Less => (i0, i1),
-                    _ => (i1, i0),
+                let ((f, f_bbox), (s, s_bbox)) = match first_bbox.compare(&second_bbox, axis) {
+                    Ordering::Less => ((i0, first_bbox), (i1, second_bbox)),
+                    _ => ((i1, second_bbox), (i0, first_bbox)),
                 };
                 Node::Branch {
                     left: Box::new(Node::Leaf {
                         shape_index: f,
-                        bbox: first_bbox,
+                        bbox: f_bbox,
                     }),
                     right: Box::new(Node::Leaf {
                         shape_index: s,
-                        bbox: second_bbox,
+                        bbox: s_bbox,
                     }),
                     bbox: surrounding_box(first_bbox, second_bbox),
                 }
@@ -89,6 +134,11 @@ impl Node {
                         r.push(*i)
                     }
                 }
+                // Rounding in the mean can leave every box on one side.
+                if l.is_empty() || r.is_empty() {
+                    l.append(&mut r);
+                    r = l.split_off(l.len() / 2);
+                }
                 let left = Self::new(objects, l, (axis + 1) % 3);
                 let right = Self::new(objects, r, (axis + 1) % 3);
                 let left_bbox = left.bbox();
@@ -100,34 +150,436 @@ impl Node {
                     bbox: surrounding_box(left_bbox, right_bbox),
                 }
             }
+        }
+    }
+
+    pub fn sah(objects: &Objects, indices: Vec<usize>) -> Self {
+        let bbox = indices.iter().fold(Aabb::empty(), |acc, i| {
+            surrounding_box(acc, objects[*i].bounding_box())
+        });
+        match indices.len() {
+            0 => return Node::Empty,
+            1 => {
+                return Node::Leaf {
+                    shape_index: indices[0],
+                    bbox,
+                }
+            }
+            _ => {}
+        }
+
+        let centroid_bounds = indices.iter().fold(Aabb::empty(), |acc, i| {
+            let c = objects[*i].bounding_box().centroid();
+            surrounding_box(acc, Aabb::new(c, c))
+        });
+
+        let n = indices.len();
+        let mut best: Option<(usize, usize, f32)> = None;
+        for axis in 0..3 {
+            let lo = centroid_bounds.box_min[axis];
+            let extent = centroid_bounds.box_max[axis] - lo;
+            if extent <= 0.0 {
+                continue;
+            }
+            let mut counts = [0usize; SAH_BINS];
+            let mut bounds = [Aabb::empty(); SAH_BINS];
+            for i in &indices {
+                let b = objects[*i].bounding_box();
+                let k = sah_bin(b.centroid()[axis], lo, extent);
+                counts[k] += 1;
+                bounds[k] = surrounding_box(bounds[k], b);
+            }
+            // Sweep from the right to get the cost of every right hand side,
+            // then from the left to combine it with the left hand side.
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let mut acc = Aabb::empty();
+            let mut count = 0;
+            for k in (1..SAH_BINS).rev() {
+                acc = surrounding_box(acc, bounds[k]);
+                count += counts[k];
+                right_area[k] = acc.surface_area();
+                right_count[k] = count;
+            }
+            let mut acc = Aabb::empty();
+            let mut count = 0;
+            for k in 1..SAH_BINS {
+                acc = surrounding_box(acc, bounds[k - 1]);
+                count += counts[k - 1];
+                if count == 0 || right_count[k] == 0 {
+                    continue;
+                }
+                let cost =
+                    acc.surface_area() * count as f32 + right_area[k] * right_count[k] as f32;
+                if best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((axis, k, cost));
+                }
+            }
+        }
+
+        let leaf_cost = n as f32;
+        let split = best.map(|(axis, k, cost)| {
+            let cost = SAH_TRAVERSAL_COST + cost / bbox.surface_area().max(f32::EPSILON);
+            (axis, k, cost)
+        });
+        let (axis, k) = match split {
+            Some((axis, k, cost)) if n > SAH_MAX_LEAF || cost < leaf_cost => (axis, k),
+            Some(_) => {
+                return Node::Group {
+                    shape_indices: indices,
+                    bbox,
+                }
+            }
+            // Every centroid coincides, so no plane separates the shapes.
+            None if n <= SAH_MAX_LEAF => {
+                return Node::Group {
+                    shape_indices: indices,
+                    bbox,
+                }
+            }
+            None => {
+                let mut l = indices;
+                let r = l.split_off(n / 2);
+                return Self::sah_branch(objects, l, r);
+            }
         };
-        node
+
+        let lo = centroid_bounds.box_min[axis];
+        let extent = centroid_bounds.box_max[axis] - lo;
+        let (l, r): (Vec<usize>, Vec<usize>) = indices
+            .into_iter()
+            .partition(|i| sah_bin(objects[*i].bounding_box().centroid()[axis], lo, extent) < k);
+        Self::sah_branch(objects, l, r)
     }
 
-    pub fn hit(&self, objects: &Objects, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
-        if !self.bbox().hit(ray, t_min, t_max) {
-            return None;
+    fn sah_branch(objects: &Objects, l: Vec<usize>, r: Vec<usize>) -> Self {
+        let (left, right) = if l.len() + r.len() > SAH_PARALLEL_THRESHOLD {
+            rayon::join(|| Self::sah(objects, l), || Self::sah(objects, r))
+        } else {
+            (Self::sah(objects, l), Self::sah(objects, r))
         };
-        match self {
+        let bbox = surrounding_box(left.bbox(), right.bbox());
+        Node::Branch {
+            left: Box::new(left),
+            right: Box::new(right),
+            bbox,
+        }
+    }
+}
+
+fn sah_bin(c: f32, lo: f32, extent: f32) -> usize {
+    (((c - lo) / extent * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+}
+
+/// A node of the flattened BVH. Interior nodes store their first child right
+/// after themselves and the offset of the second child, so a subtree occupies
+/// a contiguous run of the node array.
+#[derive(Clone, Copy, Debug)]
+pub struct LinearNode {
+    pub bbox: Aabb,
+    /// First entry in `Bvh::shape_indices` for leaves, second child for interior nodes.
+    pub offset: u32,
+    /// Number of shapes in a leaf, 0 for interior nodes.
+    pub count: u16,
+    /// Axis along which the first child precedes the second.
+    pub axis: u8,
+}
+
+/// The BVH used for ray queries: a `Node` tree flattened into depth first order.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    pub nodes: Vec<LinearNode>,
+    pub shape_indices: Vec<usize>,
+    pub depth: usize,
+}
+
+impl Bvh {
+    pub fn new(objects: &Objects, builder: Builder) -> Self {
+        Self::flatten(&build(objects, builder))
+    }
+
+    pub fn flatten(root: &Node) -> Self {
+        let mut bvh = Self::default();
+        if !matches!(root, Node::Empty) {
+            bvh.flatten_node(root, 1);
+        }
+        bvh
+    }
+
+    fn flatten_node(&mut self, node: &Node, depth: usize) -> usize {
+        self.depth = self.depth.max(depth);
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bbox: node.bbox(),
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+        match node {
+            Node::Leaf {
+                shape_index,
+                bbox: _,
+            } => {
+                self.nodes[index].offset = self.shape_indices.len() as u32;
+                self.nodes[index].count = 1;
+                self.shape_indices.push(*shape_index);
+            }
+            Node::Group {
+                shape_indices,
+                bbox: _,
+            } => {
+                self.nodes[index].offset = self.shape_indices.len() as u32;
+                self.nodes[index].count = shape_indices.len() as u16;
+                self.shape_indices.extend(shape_indices);
+            }
             Node::Branch {
                 left,
                 right,
                 bbox: _,
             } => {
-                let left_rec = left.hit(objects, ray, t_min, t_max);
-                let t = if let Some(rec) = &left_rec {
-                    rec.t
+                // Order the children along the axis that separates them most,
+                // so traversal can pick the near one from the ray direction.
+                let d = right.bbox().centroid() - left.bbox().centroid();
+                let a = d.abs();
+                let axis = if a.x >= a.y && a.x >= a.z {
+                    0
+                } else if a.y >= a.z {
+                    1
                 } else {
-                    t_max
+                    2
                 };
-                let right_rec = right.hit(objects, ray, t_min, t);
-                right_rec.or(left_rec)
+                let (first, second) = if d[axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                self.flatten_node(first, depth + 1);
+                let second_index = self.flatten_node(second, depth + 1);
+                self.nodes[index].offset = second_index as u32;
+                self.nodes[index].axis = axis as u8;
             }
-            Node::Leaf {
-                shape_index,
-                bbox: _,
-            } => objects[*shape_index].hit(ray, t_min, t_max),
-            Node::Empty => None,
+            // Only a child of a branch can get here; give it a box nothing hits.
+            Node::Empty => self.nodes[index].bbox = Aabb::empty(),
+        }
+        index
+    }
+
+    pub fn hit(&self, objects: &Objects, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        let mut rec = None;
+        self.traverse(ray, t_min, t_max, |indices, closest_so_far| {
+            for i in indices {
+                if let Some(new_rec) = objects[*i].hit(ray, t_min, *closest_so_far) {
+                    *closest_so_far = new_rec.t;
+                    rec = Some(new_rec);
+                }
+            }
+            false
+        });
+        rec
+    }
+
+    pub fn any_hit(&self, objects: &Objects, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut hit = false;
+        self.traverse(ray, t_min, t_max, |indices, t_max| {
+            hit = indices
+                .iter()
+                .any(|i| objects[*i].hit(ray, t_min, *t_max).is_some());
+            hit
+        });
+        hit
+    }
+
+    /// Calls `visit` with the shapes of every leaf the ray enters, nearer
+    /// children first. `visit` may shrink the ray's `t_max`, and returns true
+    /// to end the traversal early.
+    fn traverse<F>(&self, ray: &Ray, t_min: f32, t_max: f32, mut visit: F)
+    where
+        F: FnMut(&[usize], &mut f32) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        if self.depth <= STACK_SIZE {
+            self.traverse_with(&mut [0; STACK_SIZE], ray, t_min, t_max, &mut visit);
+        } else {
+            self.traverse_with(&mut vec![0; self.depth], ray, t_min, t_max, &mut visit);
+        }
+    }
+
+    fn traverse_with<F>(
+        &self,
+        stack: &mut [u32],
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        visit: &mut F,
+    ) where
+        F: FnMut(&[usize], &mut f32) -> bool,
+    {
+        let dir_is_neg = [
+            ray.direction.x < 0.0,
+            ray.direction.y < 0.0,
+            ray.direction.z < 0.0,
+        ];
+        let mut to_visit = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit(ray, t_min, t_max) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    let end = start + node.count as usize;
+                    if visit(&self.shape_indices[start..end], &mut t_max) {
+                        return;
+                    }
+                } else {
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[to_visit] = far as u32;
+                    to_visit += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if to_visit == 0 {
+                return;
+            }
+            to_visit -= 1;
+            current = stack[to_visit] as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::{point3, rand_point, random_unit_vector, Mat4};
+    use crate::material::Material;
+    use crate::object::Shape;
+    use crate::shapes::sphere::Sphere;
+    use crate::shapes::triangle::Triangle;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+
+    fn sphere(center: Vec3, radius: f32) -> Shape {
+        let material = Arc::new(Material::default());
+        Shape::Sphere(Sphere::new(center, radius, material, Mat4::IDENTITY))
+    }
+
+    fn triangle(a: Vec3, b: Vec3, c: Vec3) -> Shape {
+        let material = Arc::new(Material::default());
+        Shape::Triangle(Triangle::new(a, b, c, material, Mat4::IDENTITY))
+    }
+
+    fn random_scene(rng: &mut SmallRng, n: usize) -> Objects {
+        let shapes = (0..n)
+            .map(|i| {
+                let p = rand_point(rng, -10.0..10.0);
+                match i % 4 {
+                    0 => sphere(p, rng.gen_range(0.1..2.0)),
+                    1 => triangle(
+                        p,
+                        p + rand_point(rng, -3.0..3.0),
+                        p + rand_point(rng, -3.0..3.0),
+                    ),
+                    // A triangle with no area, flat against an axis.
+                    2 => triangle(p, p + Vec3::X, p + 2.0 * Vec3::X),
+                    _ => sphere(point3(1.0, 1.0, 1.0), 0.5),
+                }
+            })
+            .collect();
+        Objects(shapes)
+    }
+
+    /// Checks `hit` and `any_hit` against testing every shape.
+    fn check(objects: &Objects, rng: &mut SmallRng, rays: usize) {
+        for builder in [Builder::Mean, Builder::Sah] {
+            let bvh = Bvh::new(objects, builder);
+            for _ in 0..rays {
+                let origin = rand_point(rng, -15.0..15.0);
+                let mut direction = random_unit_vector(rng);
+                // Axis aligned rays divide by zero in the slab test.
+                if rng.gen_bool(0.1) {
+                    direction = Vec3::X;
+                }
+                let ray = Ray::new(origin, direction);
+                let t_max = if rng.gen_bool(0.5) { f32::MAX } else { 10.0 };
+                let expected = objects.hit(&ray, 0.001, t_max).map(|h| h.t);
+                let actual = bvh.hit(objects, &ray, 0.001, t_max).map(|h| h.t);
+                assert_eq!(actual, expected, "{:?} with {:?}", ray, builder);
+                assert_eq!(
+                    bvh.any_hit(objects, &ray, 0.001, t_max),
+                    expected.is_some(),
+                    "{:?} with {:?}",
+                    ray,
+                    builder
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_a_linear_scan() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        for n in [1, 2, 3, 5, 40, 300] {
+            let objects = random_scene(&mut rng, n);
+            check(&objects, &mut rng, 500);
+        }
+    }
+
+    #[test]
+    fn empty_scene_hits_nothing() {
+        let objects = Objects::default();
+        for builder in [Builder::Mean, Builder::Sah] {
+            let bvh = Bvh::new(&objects, builder);
+            assert!(bvh.nodes.is_empty());
+            let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+            assert!(bvh.hit(&objects, &ray, 0.001, f32::MAX).is_none());
+            assert!(!bvh.any_hit(&objects, &ray, 0.001, f32::MAX));
+        }
+    }
+
+    #[test]
+    fn identical_shapes_fill_leaves() {
+        let objects = Objects((0..50).map(|_| sphere(Vec3::ZERO, 1.0)).collect());
+        let mut rng = SmallRng::seed_from_u64(11);
+        check(&objects, &mut rng, 200);
+    }
+
+    #[test]
+    fn deep_trees_overflow_the_stack_array() {
+        // A chain of branches, each with one shape to the left of the rest.
+        let objects = Objects(
+            (0..100)
+                .map(|i| sphere(point3(3.0 * i as f32, 0.0, 0.0), 1.0))
+                .collect(),
+        );
+        let leaf = |i: usize| Node::Leaf {
+            shape_index: i,
+            bbox: objects[i].bounding_box(),
+        };
+        let root = (0..99).rev().fold(leaf(99), |rest, i| Node::Branch {
+            bbox: surrounding_box(objects[i].bounding_box(), rest.bbox()),
+            left: Box::new(leaf(i)),
+            right: Box::new(rest),
+        });
+        let bvh = Bvh::flatten(&root);
+        assert!(bvh.depth > STACK_SIZE);
+        let mut rng = SmallRng::seed_from_u64(13);
+        for _ in 0..500 {
+            let origin = rand_point(&mut rng, -5.0..300.0);
+            let ray = Ray::new(origin, random_unit_vector(&mut rng));
+            let expected = objects.hit(&ray, 0.001, f32::MAX).map(|h| h.t);
+            let actual = bvh.hit(&objects, &ray, 0.001, f32::MAX).map(|h| h.t);
+            assert_eq!(actual, expected, "{:?}", ray);
+            assert_eq!(
+                bvh.any_hit(&objects, &ray, 0.001, f32::MAX),
+                expected.is_some()
+            );
         }
     }
 }
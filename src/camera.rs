@@ -1,5 +1,35 @@
-use crate::geom::{cross, degrees_to_radians, point3, Point3, Ray, Vec3};
+use crate::geom::{
+    concentric_disk, cross, degrees_to_radians, dot, point3, Point3, Ray, Vec2, Vec3, PI,
+};
 
+/// How positions on the image map to camera rays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens with a vertical field of view in degrees.
+    Perspective { fovy: f32 },
+    /// Parallel rays along the view direction, covering `height` world
+    /// units vertically.
+    Orthographic { height: f32 },
+    /// Equidistant fisheye with a field of view in degrees across the image
+    /// circle, which fills the shorter side of the image.
+    Fisheye { fov: f32 },
+    /// Full 360 by 180 degree panorama, centred on the view direction.
+    Equirectangular,
+}
+
+/// Thin lens settings, applied to cameras as they are created.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lens {
+    pub aperture: f32,
+    /// Focus distance, or the distance to `look_at` if `None`.
+    pub focus: Option<f32>,
+    pub autofocus: bool,
+}
+
+/// A camera looking from `look_from` towards `look_at`. With a positive
+/// `aperture`, the lens radius, perspective and orthographic cameras are thin
+/// lenses focused on the plane `focus_distance` in front of them. Cameras
+/// with `autofocus` set are focused by `World::autofocus`.
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub width: f32,
@@ -10,10 +40,17 @@ pub struct Camera {
     pub u: Vec3,
     pub v: Vec3,
     pub w: Vec3,
-    tan_fovy_2: f32,
+    pub projection: Projection,
+    /// Side of a pixel on the image plane a unit in front of a perspective
+    /// camera, or in world units for an orthographic one, set by `resize`.
+    pub pixel_size: f32,
+    pub aperture: f32,
+    pub focus_distance: f32,
+    pub autofocus: bool,
 }
 
 impl Camera {
+    /// A perspective camera with a vertical field of view of `theta` degrees.
     pub fn new(
         width: f32,
         height: f32,
@@ -21,12 +58,23 @@ impl Camera {
         look_at: Point3,
         up: Vec3,
         theta: f32,
+    ) -> Self {
+        let projection = Projection::Perspective { fovy: theta };
+        Self::with_view(width, height, look_from, look_at, up, projection)
+    }
+
+    pub fn with_view(
+        width: f32,
+        height: f32,
+        look_from: Point3,
+        look_at: Point3,
+        up: Vec3,
+        projection: Projection,
     ) -> Self {
         let w = (look_from - look_at).normalize();
         let u = cross(up, w).normalize();
         let v = cross(w, u);
-        let tan_fovy_2 = degrees_to_radians(theta / 2.0).tan();
-        Self {
+        let mut camera = Self {
             width,
             height,
             look_from,
@@ -35,17 +83,101 @@ impl Camera {
             u,
             v,
             w,
-            tan_fovy_2,
+            projection,
+            pixel_size: 0.0,
+            aperture: 0.0,
+            focus_distance: (look_from - look_at).length(),
+            autofocus: false,
+        };
+        camera.resize(width, height);
+        camera
+    }
+
+    /// Sets the image size, keeping the field of view.
+    pub fn resize(&mut self, width: f32, height: f32) {
+        self.width = width;
+        self.height = height;
+        self.pixel_size = match self.projection {
+            Projection::Perspective { fovy } => 2.0 * degrees_to_radians(fovy / 2.0).tan() / height,
+            Projection::Orthographic { height: extent } => extent / height,
+            Projection::Fisheye { .. } | Projection::Equirectangular => 0.0,
+        };
+    }
+
+    pub fn with_lens(mut self, lens: Lens) -> Self {
+        self.aperture = lens.aperture;
+        if let Some(focus) = lens.focus {
+            self.focus_distance = focus;
         }
+        self.autofocus = lens.autofocus;
+        self
+    }
+
+    /// Ray through the centre of the pixel in row `i`, column `j`.
+    pub fn get_ray(&self, i: f32, j: f32) -> Option<Ray> {
+        self.sample_ray(i + 0.5, j + 0.5)
     }
 
-    pub fn get_ray(&self, i: f32, j: f32) -> Ray {
-        let origin = self.look_from;
-        let tan_fovx_2 = self.tan_fovy_2 * self.width / self.height;
-        let alpha = tan_fovx_2 * 2.0 / self.width * (j + 0.5 - self.width / 2.0);
-        let beta = self.tan_fovy_2 * 2.0 / self.height * (self.height / 2.0 - i - 0.5);
-        let direction = (alpha * self.u + beta * self.v - self.w).normalize();
-        Ray { origin, direction }
+    /// Ray through the raster position `y` down and `x` across the image,
+    /// where pixel `(i, j)` covers `[i, i + 1) x [j, j + 1)`. There is no ray
+    /// outside the image circle of a fisheye.
+    pub fn sample_ray(&self, y: f32, x: f32) -> Option<Ray> {
+        // Position on the image with y up, in [-1, 1] across the shorter side.
+        let half = 0.5 * self.width.min(self.height);
+        let px = (x - self.width / 2.0) / half;
+        let py = (self.height / 2.0 - y) / half;
+        let ray = match self.projection {
+            Projection::Perspective { .. } => {
+                let alpha = self.pixel_size * (x - self.width / 2.0);
+                let beta = self.pixel_size * (self.height / 2.0 - y);
+                let direction = (alpha * self.u + beta * self.v - self.w).normalize();
+                Ray::new(self.look_from, direction)
+            }
+            Projection::Orthographic { .. } => {
+                let alpha = self.pixel_size * (x - self.width / 2.0);
+                let beta = self.pixel_size * (self.height / 2.0 - y);
+                let origin = self.look_from + alpha * self.u + beta * self.v;
+                Ray::new(origin, -self.w)
+            }
+            Projection::Fisheye { fov } => {
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * degrees_to_radians(fov / 2.0);
+                let phi = py.atan2(px);
+                let direction =
+                    theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+                Ray::new(self.look_from, direction)
+            }
+            Projection::Equirectangular => {
+                let phi = 2.0 * PI * (x / self.width - 0.5);
+                let elevation = PI * (0.5 - y / self.height);
+                let direction = elevation.cos() * (phi.sin() * self.u - phi.cos() * self.w)
+                    + elevation.sin() * self.v;
+                Ray::new(self.look_from, direction)
+            }
+        };
+        Some(ray)
+    }
+
+    /// Ray through the raster position `(y, x)` from the point of the lens
+    /// picked by `u_lens` in the unit square. Pinhole cameras, fisheyes and
+    /// panoramas ignore `u_lens`.
+    pub fn lens_ray(&self, y: f32, x: f32, u_lens: Vec2) -> Option<Ray> {
+        let ray = self.sample_ray(y, x)?;
+        let thin_lens = matches!(
+            self.projection,
+            Projection::Perspective { .. } | Projection::Orthographic { .. }
+        );
+        if self.aperture <= 0.0 || !thin_lens {
+            return Some(ray);
+        }
+        let t = self.focus_distance / dot(ray.direction, -self.w);
+        let focus = ray.at(t);
+        let lens = self.aperture * concentric_disk(u_lens);
+        let origin = ray.origin + lens.x * self.u + lens.y * self.v;
+        Some(Ray::new(origin, (focus - origin).normalize()))
     }
 }
 
@@ -61,3 +193,32 @@ impl Default for Camera {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::vec3;
+
+    #[test]
+    fn resizing_keeps_the_vertical_field_of_view() {
+        let mut camera = Camera::new(
+            640.0,
+            480.0,
+            point3(0.0, 0.0, 0.0),
+            point3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            90.0,
+        );
+        for (width, height) in [(640.0, 480.0), (100.0, 300.0)] {
+            camera.resize(width, height);
+            let top = camera.sample_ray(0.0, width / 2.0).unwrap().direction;
+            let right = camera.sample_ray(height / 2.0, width).unwrap().direction;
+            assert!((top.y / -top.z - 1.0).abs() < 1e-5, "{:?}", top);
+            assert!(
+                (right.x / -right.z - width / height).abs() < 1e-5,
+                "{:?}",
+                right
+            );
+        }
+    }
+}
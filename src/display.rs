@@ -0,0 +1,183 @@
+use crate::film::Film;
+use crate::geom::{luminance, Color};
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+/// Operator compressing exposed radiance into `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance, keeping the hue.
+    Reinhard,
+    /// Reinhard with luminance `white` and above mapped to 1.
+    ExtendedReinhard { white: f32 },
+    /// Narkowicz's fit of the ACES filmic curve, per channel.
+    Aces,
+}
+
+impl ToneMap {
+    /// The operator called `name`. Extended Reinhard takes a white point,
+    /// which is 4 unless one is given.
+    pub fn new(name: &str, white: Option<f32>) -> Result<Self> {
+        let tone_map = match name {
+            "clamp" => ToneMap::Clamp,
+            "reinhard" => ToneMap::Reinhard,
+            "reinhard-extended" => ToneMap::ExtendedReinhard {
+                white: white.unwrap_or(4.0),
+            },
+            "aces" => ToneMap::Aces,
+            _ => return Err(anyhow!("unknown tone map {}", name)),
+        };
+        Ok(tone_map)
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let c = c.max(Color::ZERO);
+        let mapped = match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                scale_luminance(c, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMap::Aces => {
+                let f = |x: f32| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                Color::new(f(c.x), f(c.y), f(c.z))
+            }
+        };
+        mapped.min(Color::ONE)
+    }
+}
+
+fn scale_luminance(c: Color, f: impl Fn(f32) -> f32) -> Color {
+    let l = luminance(c);
+    if l > 0.0 {
+        c * (f(l) / l)
+    } else {
+        c
+    }
+}
+
+/// Transfer function from linear values to the stored code values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Linear,
+    Srgb,
+}
+
+impl Encoding {
+    pub fn encode(&self, v: f32) -> f32 {
+        match self {
+            Encoding::Linear => v,
+            Encoding::Srgb => {
+                if v <= 0.0031308 {
+                    12.92 * v
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "linear" => Ok(Encoding::Linear),
+            "srgb" => Ok(Encoding::Srgb),
+            _ => Err(anyhow!("unknown encoding {}", s)),
+        }
+    }
+}
+
+/// Turns the radiance in a film into integer pixels: scale by `exposure`
+/// stops, tone map, encode, then quantize to `bit_depth` bits, with
+/// triangular noise of one code value added first if `dither` is set. The
+/// default leaves radiance as it is and clamps it, like the course's
+/// reference images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    pub encoding: Encoding,
+    pub dither: bool,
+    pub bit_depth: u8,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_map: ToneMap::default(),
+            encoding: Encoding::default(),
+            dither: false,
+            bit_depth: 8,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Display value of radiance `c`, in `[0, 1]`.
+    pub fn map(&self, c: Color) -> Color {
+        let c = self.tone_map.apply(c * self.exposure.exp2());
+        Color::new(
+            self.encoding.encode(c.x),
+            self.encoding.encode(c.y),
+            self.encoding.encode(c.z),
+        )
+    }
+
+    /// 8 bit RGB data, row by row from the top of the image.
+    pub fn to_rgb8(&self, film: &Film) -> Vec<u8> {
+        self.quantize(film, 255.0)
+            .into_iter()
+            .map(|v| v as u8)
+            .collect()
+    }
+
+    /// 16 bit RGB data, row by row from the top of the image.
+    pub fn to_rgb16(&self, film: &Film) -> Vec<u16> {
+        self.quantize(film, 65535.0)
+            .into_iter()
+            .map(|v| v as u16)
+            .collect()
+    }
+
+    fn quantize(&self, film: &Film, max: f32) -> Vec<f32> {
+        let mut data = Vec::with_capacity((3 * film.width * film.height) as usize);
+        for j in 0..film.height {
+            for i in 0..film.width {
+                let c = self.map(film.pixel(i, j));
+                for (k, v) in [c.x, c.y, c.z].into_iter().enumerate() {
+                    let noise = if self.dither {
+                        let index = 3 * (j as u64 * film.width as u64 + i as u64) + k as u64;
+                        triangle(index)
+                    } else {
+                        0.0
+                    };
+                    data.push(((max + 0.999) * v + noise).clamp(0.0, max));
+                }
+            }
+        }
+        data
+    }
+}
+
+/// Triangularly distributed noise in `(-1, 1)`, fixed for each `index`.
+fn triangle(index: u64) -> f32 {
+    let h = hash(index);
+    let u1 = (h >> 40) as f32 / (1u64 << 24) as f32;
+    let u2 = ((h >> 16) & 0xff_ffff) as f32 / (1u64 << 24) as f32;
+    u1 + u2 - 1.0
+}
+
+/// SplitMix64 finalizer.
+fn hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
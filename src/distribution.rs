@@ -0,0 +1,137 @@
+use crate::geom::{vec2, Vec2};
+
+/// Piecewise constant density on `[0, 1)` proportional to `func`.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    pub func: Vec<f32>,
+    /// Running integral of `func`, normalized to end at 1.
+    pub cdf: Vec<f32>,
+    /// Integral of `func` over `[0, 1)`.
+    pub integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f32;
+        }
+        let integral = cdf[n];
+        if integral > 0.0 {
+            for c in &mut cdf[1..] {
+                *c /= integral;
+            }
+        } else {
+            // Nothing to favour, so sample uniformly.
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.func.is_empty()
+    }
+
+    /// A point in `[0, 1)` for `u`, its density and the segment it is in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.len();
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = ((i as f32 + du) / n as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(i), i)
+    }
+
+    /// Density of segment `i`.
+    pub fn pdf(&self, i: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise constant density on the unit square proportional to a grid of
+/// `func[v * width + u]`, sampled as a marginal density of rows and a
+/// conditional one within each row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// A point in the unit square for `u` and its density.
+    pub fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.x);
+        (vec2(x, y), pdf_x * pdf_y)
+    }
+
+    /// Density at `p` in the unit square.
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let height = self.marginal.len();
+        let row = ((p.y * height as f32) as usize).min(height - 1);
+        let conditional = &self.conditional[row];
+        let width = conditional.len();
+        let column = ((p.x * width as f32) as usize).min(width - 1);
+        conditional.pdf(column) * self.marginal.pdf(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn densities_integrate_to_one() {
+        for func in [vec![1.0, 3.0, 0.0, 4.0, 2.0], vec![0.0; 4], vec![2.5]] {
+            let distribution = Distribution1D::new(func);
+            let n = distribution.len();
+            let integral: f32 = (0..n).map(|i| distribution.pdf(i) / n as f32).sum();
+            assert!((integral - 1.0).abs() < 1e-5, "integral {}", integral);
+            assert!((distribution.cdf[n] - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn samples_follow_the_density() {
+        let distribution = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        for k in 0..64 {
+            let u = (k as f32 + 0.5) / 64.0;
+            let (x, pdf, i) = distribution.sample(u);
+            assert_eq!(i, (x * 4.0) as usize);
+            assert_eq!(pdf, distribution.pdf(i));
+            assert!(pdf > 0.0);
+        }
+    }
+}
@@ -1,57 +1,107 @@
-use crate::bvh::Node;
-use crate::camera::Camera;
+use crate::bsdf::{Fresnel, Lobe, Metal};
+use crate::bvh::{Builder, Bvh};
+use crate::camera::{Camera, Lens, Projection};
+use crate::display::DisplayTransform;
+use crate::environment::Environment;
+use crate::filter::Filter;
 use crate::geom::{degrees_to_radians, point3, vec3, Color, Mat4, Point3, Vec3};
-use crate::light::Light;
-use crate::material::Material;
+use crate::ies::IesProfile;
+use crate::light::{Light, LightSampling};
+use crate::material::{Material, ShadingModel};
 use crate::object::{Objects, Shape};
-use crate::scene::World;
+use crate::sampler::SamplerKind;
+use crate::scene::{View, World};
 use crate::shapes::sphere::Sphere;
 use crate::shapes::triangle::Triangle;
+use crate::sky::Sky;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 pub struct Edsl {
     pub width: f32,
     pub height: f32,
-    pub ambient: Vec3,
     pub objects: Objects,
     pub lights: Vec<Light>,
-    pub camera: Camera,
+    pub light_samples: u32,
+    pub light_sampling: LightSampling,
+    pub environment: Option<Arc<Environment>>,
+    pub cameras: Vec<(String, Camera)>,
+    pub lens: Lens,
     pub transforms: Vec<Mat4>,
     pub vertices: Vec<Vec3>,
-    pub current_material: Material,
+    pub current_material: Arc<Material>,
     pub attenuation: [f32; 3],
     pub max_depth: i32,
+    pub shading: ShadingModel,
+    pub builder: Builder,
+    pub samples: u32,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub seed: u64,
+    pub output: Option<PathBuf>,
+    pub display: DisplayTransform,
 }
 
 impl Edsl {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: f32,
         height: f32,
-        ambient: Vec3,
         objects: Objects,
         lights: Vec<Light>,
-        camera: Camera,
+        light_samples: u32,
+        light_sampling: LightSampling,
+        environment: Option<Arc<Environment>>,
+        cameras: Vec<(String, Camera)>,
+        lens: Lens,
         transforms: Vec<Mat4>,
         vertices: Vec<Vec3>,
-        current_material: Material,
+        current_material: Arc<Material>,
         attenuation: [f32; 3],
         max_depth: i32,
+        shading: ShadingModel,
+        builder: Builder,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+        seed: u64,
+        output: Option<PathBuf>,
+        display: DisplayTransform,
     ) -> Self {
         Self {
             width,
             height,
-            ambient,
             objects,
             lights,
-            camera,
+            light_samples,
+            light_sampling,
+            environment,
+            cameras,
+            lens,
             transforms,
             vertices,
             current_material,
             attenuation,
             max_depth,
+            shading,
+            builder,
+            samples,
+            sampler,
+            filter,
+            seed,
+            output,
+            display,
         }
     }
 
+    pub fn output(&mut self, path: impl Into<PathBuf>) {
+        self.output = Some(path.into());
+    }
+
+    pub fn display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
     pub fn size(&mut self, w: f32, h: f32) {
         self.width = w;
         self.height = h;
@@ -61,13 +111,134 @@ impl Edsl {
         self.max_depth = d;
     }
 
+    pub fn shading(&mut self, shading: ShadingModel) {
+        self.shading = shading;
+    }
+
+    pub fn samples(&mut self, n: u32) {
+        self.samples = n;
+    }
+
+    pub fn sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler;
+    }
+
+    pub fn filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn bvh(&mut self, builder: Builder) {
+        self.builder = builder;
+    }
+
+    /// Adds a perspective camera named camera1, camera2 and so on in order.
     pub fn camera(&mut self, look_from: Point3, look_at: Point3, up: Point3, fov: f32) {
-        self.camera = Camera::new(self.width, self.height, look_from, look_at, up, fov);
+        let name = format!("camera{}", self.cameras.len() + 1);
+        self.named_camera(&name, look_from, look_at, up, fov);
+    }
+
+    pub fn named_camera(
+        &mut self,
+        name: &str,
+        look_from: Point3,
+        look_at: Point3,
+        up: Point3,
+        fov: f32,
+    ) {
+        let projection = Projection::Perspective { fovy: fov };
+        self.view(name, look_from, look_at, up, projection);
+    }
+
+    /// Adds an orthographic camera covering `height` world units vertically.
+    pub fn ortho_camera(&mut self, look_from: Point3, look_at: Point3, up: Point3, height: f32) {
+        let name = format!("camera{}", self.cameras.len() + 1);
+        self.named_ortho_camera(&name, look_from, look_at, up, height);
+    }
+
+    pub fn named_ortho_camera(
+        &mut self,
+        name: &str,
+        look_from: Point3,
+        look_at: Point3,
+        up: Point3,
+        height: f32,
+    ) {
+        let projection = Projection::Orthographic { height };
+        self.view(name, look_from, look_at, up, projection);
+    }
+
+    /// Adds an equidistant fisheye camera with a field of view of `fov` degrees.
+    pub fn fisheye_camera(&mut self, look_from: Point3, look_at: Point3, up: Point3, fov: f32) {
+        let name = format!("camera{}", self.cameras.len() + 1);
+        self.named_fisheye_camera(&name, look_from, look_at, up, fov);
+    }
+
+    pub fn named_fisheye_camera(
+        &mut self,
+        name: &str,
+        look_from: Point3,
+        look_at: Point3,
+        up: Point3,
+        fov: f32,
+    ) {
+        let projection = Projection::Fisheye { fov };
+        self.view(name, look_from, look_at, up, projection);
+    }
+
+    /// Adds a 360 degree panoramic camera centred on `look_at`.
+    pub fn equirect_camera(&mut self, look_from: Point3, look_at: Point3, up: Point3) {
+        let name = format!("camera{}", self.cameras.len() + 1);
+        self.named_equirect_camera(&name, look_from, look_at, up);
+    }
+
+    pub fn named_equirect_camera(
+        &mut self,
+        name: &str,
+        look_from: Point3,
+        look_at: Point3,
+        up: Point3,
+    ) {
+        self.view(name, look_from, look_at, up, Projection::Equirectangular);
+    }
+
+    fn view(
+        &mut self,
+        name: &str,
+        look_from: Point3,
+        look_at: Point3,
+        up: Point3,
+        projection: Projection,
+    ) {
+        let camera = Camera::with_view(self.width, self.height, look_from, look_at, up, projection)
+            .with_lens(self.lens);
+        self.cameras.push((name.to_string(), camera));
+    }
+
+    /// Lens radius of the cameras added after this. Cameras already added
+    /// keep the lens they were added with.
+    pub fn aperture(&mut self, radius: f32) {
+        self.lens.aperture = radius;
+    }
+
+    /// Focus distance of the cameras added after this, leaving earlier
+    /// ones as they are.
+    pub fn focus(&mut self, distance: f32) {
+        self.lens.focus = Some(distance);
+        self.lens.autofocus = false;
+    }
+
+    /// Focuses the cameras added after this on whatever is at the centre of
+    /// their images.
+    pub fn autofocus(&mut self) {
+        self.lens.autofocus = true;
     }
 
     pub fn ambient(&mut self, r: f32, g: f32, b: f32) {
-        let a = Color::new(r, g, b);
-        self.ambient = a;
+        self.material().ambient = Color::new(r, g, b);
     }
 
     pub fn directional(&mut self, x: f32, y: f32, z: f32, r: f32, g: f32, b: f32) {
@@ -78,8 +249,125 @@ impl Edsl {
         self.lights.push(Light::Point { x, y, z, r, g, b });
     }
 
+    /// A spot light at full strength within `inner` degrees of `direction`,
+    /// fading out at `outer` degrees.
+    pub fn spot(
+        &mut self,
+        position: Point3,
+        direction: Vec3,
+        inner: f32,
+        outer: f32,
+        color: Color,
+    ) {
+        self.lights.push(Light::Spot {
+            position,
+            direction,
+            inner,
+            outer,
+            color,
+            up: Vec3::ZERO,
+            profile: None,
+        });
+    }
+
+    /// A spot light whose intensity also follows `profile`, with its nadir
+    /// along `direction` and its 0 degree horizontal angle towards `up`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn ies_spot(
+        &mut self,
+        position: Point3,
+        direction: Vec3,
+        inner: f32,
+        outer: f32,
+        color: Color,
+        profile: Arc<IesProfile>,
+        up: Vec3,
+    ) {
+        self.lights.push(Light::Spot {
+            position,
+            direction,
+            inner,
+            outer,
+            color,
+            up,
+            profile: Some(profile),
+        });
+    }
+
+    /// Shadow rays taken towards the area lights added after this.
+    pub fn light_samples(&mut self, n: u32) {
+        self.light_samples = n;
+    }
+
+    pub fn light_sampling(&mut self, sampling: LightSampling) {
+        self.light_sampling = sampling;
+    }
+
+    pub fn quad_light(&mut self, corner: Point3, edge1: Vec3, edge2: Vec3, radiance: Color) {
+        self.area_light(Light::Quad {
+            corner,
+            edge1,
+            edge2,
+            radiance,
+            samples: self.light_samples,
+            sampling: self.light_sampling,
+        });
+    }
+
+    pub fn disc_light(&mut self, center: Point3, normal: Vec3, radius: f32, radiance: Color) {
+        self.area_light(Light::Disc {
+            center,
+            normal: normal.normalize(),
+            radius,
+            radiance,
+            samples: self.light_samples,
+            sampling: self.light_sampling,
+        });
+    }
+
+    pub fn sphere_light(&mut self, center: Point3, radius: f32, radiance: Color) {
+        self.area_light(Light::Sphere {
+            center,
+            radius,
+            radiance,
+            samples: self.light_samples,
+            sampling: self.light_sampling,
+        });
+    }
+
+    /// Lights the scene with `environment` and shows it behind it, in place
+    /// of any environment set before.
+    pub fn environment(&mut self, environment: Environment) {
+        let map = Arc::new(environment);
+        self.lights
+            .retain(|l| !matches!(l, Light::Environment { .. }));
+        self.lights.push(Light::Environment {
+            map: map.clone(),
+            samples: self.light_samples,
+        });
+        self.environment = Some(map);
+    }
+
+    /// Lights the scene with `sky` and its sun, a disc of `sun_radius`
+    /// degrees, in place of any environment or sky set before.
+    pub fn sky(&mut self, sky: Sky, sun_radius: f32) {
+        self.lights.retain(|l| !matches!(l, Light::Sun { .. }));
+        self.environment(sky.environment(512, 256));
+        self.lights.push(sky.sun(sun_radius, self.light_samples));
+    }
+
+    fn area_light(&mut self, light: Light) {
+        self.objects.0.extend(light.shapes());
+        self.lights.push(light);
+    }
+
+    /// The current material, copied first if shapes already share it.
+    fn material(&mut self) -> &mut Material {
+        Arc::make_mut(&mut self.current_material)
+    }
+
     pub fn diffuse(&mut self, r: f32, g: f32, b: f32) {
-        self.current_material.diffuse = Color::new(r, g, b);
+        self.material().diffuse = Color::new(r, g, b);
     }
 
     pub fn attenuation(&mut self, c: f32, l: f32, q: f32) {
@@ -87,15 +375,70 @@ impl Edsl {
     }
 
     pub fn specular(&mut self, r: f32, g: f32, b: f32) {
-        self.current_material.specular = Color::new(r, g, b);
+        self.material().specular = Color::new(r, g, b);
     }
 
     pub fn shininess(&mut self, s: f32) {
-        self.current_material.shininess = s;
+        self.material().shininess = s;
     }
 
     pub fn emission(&mut self, r: f32, g: f32, b: f32) {
-        self.current_material.emission = Color::new(r, g, b);
+        self.material().emission = Color::new(r, g, b);
+    }
+
+    pub fn ior(&mut self, ior: f32) {
+        self.material().ior = ior;
+    }
+
+    pub fn transmission(&mut self, r: f32, g: f32, b: f32) {
+        self.material().transmission = Color::new(r, g, b);
+    }
+
+    /// Beer-Lambert absorption per unit length inside the material.
+    pub fn absorption(&mut self, r: f32, g: f32, b: f32) {
+        self.material().absorption = Color::new(r, g, b);
+    }
+
+    /// Adds a lobe, which replaces the course's colours in how the current
+    /// material scatters light.
+    pub fn lobe(&mut self, lobe: Lobe) {
+        self.material().lobes.push(lobe);
+    }
+
+    /// Puts a clear coat over the lobes added so far.
+    pub fn coat(&mut self, ior: f32) {
+        let base = std::mem::take(&mut self.material().lobes);
+        self.material().lobes.push(Lobe::Coated { ior, base });
+    }
+
+    /// Adds a metal with complex index of refraction `eta + i k` and GGX
+    /// roughness.
+    pub fn conductor(&mut self, eta: Color, k: Color, roughness: f32) {
+        self.lobe(Lobe::Conductor {
+            fresnel: Fresnel::Conductor { eta, k },
+            roughness,
+        });
+    }
+
+    pub fn metal(&mut self, metal: Metal, roughness: f32) {
+        self.lobe(Lobe::Conductor {
+            fresnel: metal.fresnel(),
+            roughness,
+        });
+    }
+
+    /// Adds frosted glass letting through `r g b` of the refracted light.
+    pub fn rough_dielectric(&mut self, ior: f32, r: f32, g: f32, b: f32, roughness: f32) {
+        self.lobe(Lobe::Dielectric {
+            ior,
+            transmission: Color::new(r, g, b),
+            roughness,
+        });
+    }
+
+    /// Goes back to the course's colours.
+    pub fn clear_lobes(&mut self) {
+        self.material().lobes.clear();
     }
 
     pub fn vertex(&mut self, x: f32, y: f32, z: f32) {
@@ -106,7 +449,7 @@ impl Edsl {
         let s = Sphere::new(
             point3(x, y, z),
             r,
-            Arc::new(self.current_material.clone()),
+            self.current_material.clone(),
             *self.transforms.last().unwrap(),
         );
         self.objects.0.push(Shape::Sphere(s));
@@ -117,7 +460,7 @@ impl Edsl {
             self.vertices[a],
             self.vertices[b],
             self.vertices[c],
-            Arc::new(self.current_material.clone()),
+            self.current_material.clone(),
             *self.transforms.last().unwrap(),
         );
         self.objects.0.push(Shape::Triangle(t));
@@ -168,17 +511,26 @@ impl Edsl {
     }
 
     pub fn run(self) -> World {
-        let indices: Vec<usize> = (0..self.objects.len()).collect();
-        let nodes = Node::new(&self.objects, indices, 0);
-        World {
-            camera: self.camera,
-            bvh_node: nodes,
+        let bvh = Bvh::new(&self.objects, self.builder);
+        let output = self.output.unwrap_or_else(|| PathBuf::from("image.png"));
+        let mut world = World {
+            views: View::from_cameras(self.cameras, &output),
+            bvh,
             objects: self.objects,
             lights: self.lights,
-            ambient: self.ambient,
+            environment: self.environment,
             attenuation: self.attenuation,
             max_depth: self.max_depth,
-        }
+            shading: self.shading,
+            samples: self.samples,
+            sampler: self.sampler,
+            filter: self.filter,
+            seed: self.seed,
+            display: self.display,
+            progress: false,
+        };
+        world.autofocus();
+        world
     }
 }
 
@@ -188,15 +540,26 @@ impl Default for Edsl {
         Self {
             width: camera.width,
             height: camera.height,
-            ambient: Default::default(),
             objects: Default::default(),
             lights: Default::default(),
-            camera,
+            light_samples: 1,
+            light_sampling: LightSampling::default(),
+            environment: None,
+            cameras: Vec::new(),
+            lens: Lens::default(),
             transforms: vec![Mat4::IDENTITY],
             vertices: Default::default(),
             current_material: Default::default(),
             attenuation: [1.0, 0.0, 0.0],
             max_depth: 5,
+            shading: ShadingModel::default(),
+            builder: Builder::default(),
+            samples: 1,
+            sampler: SamplerKind::default(),
+            filter: Filter::default(),
+            seed: 0,
+            output: None,
+            display: DisplayTransform::default(),
         }
     }
 }
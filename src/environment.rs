@@ -0,0 +1,137 @@
+use crate::distribution::Distribution2D;
+use crate::geom::{degrees_to_radians, luminance, vec2, vec3, Color, Vec2, Vec3, BLACK, PI};
+use crate::io::read_radiance;
+use anyhow::Result;
+use std::path::Path;
+
+/// Light arriving from infinitely far away, looked up in an equirectangular
+/// image whose top row is straight up along +y and whose centre faces -z.
+/// Directions are picked in proportion to the map's luminance.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    /// Radiance, row by row from the top of the image.
+    pub pixels: Vec<Color>,
+    /// Scale of the radiance in the image.
+    pub intensity: f32,
+    /// Degrees the map is turned about +y.
+    pub rotation: f32,
+    pub distribution: Distribution2D,
+}
+
+impl Environment {
+    pub fn new(pixels: Vec<Color>, width: u32, height: u32, intensity: f32, rotation: f32) -> Self {
+        // Rows near the poles cover less of the sphere.
+        let func: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| {
+                let row = i / width as usize;
+                let sin_theta = (PI * (row as f32 + 0.5) / height as f32).sin();
+                luminance(c) * sin_theta
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, width as usize, height as usize);
+        Self {
+            width,
+            height,
+            pixels,
+            intensity,
+            rotation,
+            distribution,
+        }
+    }
+
+    /// Loads a pfm or hdr map.
+    pub fn load(path: impl AsRef<Path>, intensity: f32, rotation: f32) -> Result<Self> {
+        let (data, width, height) = read_radiance(path)?;
+        let pixels = data
+            .chunks_exact(3)
+            .map(|c| Color::new(c[0], c[1], c[2]).max(BLACK))
+            .collect();
+        Ok(Self::new(pixels, width, height, intensity, rotation))
+    }
+
+    /// Radiance arriving from `direction`, which need not be normalized.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        self.lookup(self.uv(direction))
+    }
+
+    /// A unit direction picked for `u`, the radiance from it and its density
+    /// in solid angle, or None for a direction with no density.
+    pub fn sample(&self, u: Vec2) -> Option<(Vec3, Color, f32)> {
+        let (uv, pdf) = self.distribution.sample(u);
+        let sin_theta = (PI * uv.y).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some((
+            self.direction(uv),
+            self.lookup(uv),
+            pdf / (2.0 * PI * PI * sin_theta),
+        ))
+    }
+
+    /// Density in solid angle of `sample` picking `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let uv = self.uv(direction);
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn lookup(&self, uv: Vec2) -> Color {
+        let x = ((uv.x * self.width as f32) as u32).min(self.width - 1);
+        let y = ((uv.y * self.height as f32) as u32).min(self.height - 1);
+        self.intensity * self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn uv(&self, direction: Vec3) -> Vec2 {
+        let d = direction.normalize();
+        let phi = d.x.atan2(-d.z) - degrees_to_radians(self.rotation);
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        vec2((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+    }
+
+    fn direction(&self, uv: Vec2) -> Vec3 {
+        let phi = 2.0 * PI * (uv.x - 0.5) + degrees_to_radians(self.rotation);
+        let theta = PI * uv.y;
+        vec3(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_density_matches_pdf() {
+        let (width, height) = (8, 4);
+        let pixels = (0..width * height)
+            .map(|i| Color::new((i % 5) as f32, (i % 3) as f32, 0.5))
+            .collect();
+        let environment = Environment::new(pixels, width, height, 2.0, 30.0);
+        for j in 0..16 {
+            for i in 0..16 {
+                let u = vec2((i as f32 + 0.3) / 16.0, (j as f32 + 0.6) / 16.0);
+                let (direction, radiance, pdf) = environment.sample(u).unwrap();
+                let expected = environment.pdf(direction);
+                assert!(
+                    (pdf - expected).abs() <= 1e-3 * expected,
+                    "pdf {} for {:?}, not {}",
+                    pdf,
+                    u,
+                    expected
+                );
+                assert_eq!(radiance, environment.radiance(direction));
+            }
+        }
+    }
+}
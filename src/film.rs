@@ -0,0 +1,127 @@
+use crate::filter::Filter;
+use crate::geom::{Color, BLACK};
+
+/// Smallest filter weight a pixel's samples must sum to for it to be resolved.
+const MIN_WEIGHT: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    sum: Color,
+    weight: f32,
+}
+
+/// Accumulates filtered radiance samples into an image. Pixel `(i, j)` is
+/// column `i` and row `j`, with its centre at raster position `(i + 0.5, j + 0.5)`.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Filter,
+    pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![
+                Pixel {
+                    sum: BLACK,
+                    weight: 0.0,
+                };
+                (width * height) as usize
+            ],
+        }
+    }
+
+    /// Splats a sample taken at raster position `(x, y)` into every pixel
+    /// whose filter covers it.
+    pub fn add_sample(&mut self, x: f32, y: f32, color: Color) {
+        let radius = self.filter.radius();
+        let i0 = ((x - 0.5 - radius).ceil() as i64).max(0);
+        let j0 = ((y - 0.5 - radius).ceil() as i64).max(0);
+        let i1 = ((x - 0.5 + radius).floor() as i64).min(self.width as i64 - 1);
+        let j1 = ((y - 0.5 + radius).floor() as i64).min(self.height as i64 - 1);
+        for j in j0..=j1 {
+            for i in i0..=i1 {
+                let w = self
+                    .filter
+                    .evaluate(x - (i as f32 + 0.5), y - (j as f32 + 0.5));
+                if w != 0.0 {
+                    let pixel = &mut self.pixels[(j * self.width as i64 + i) as usize];
+                    pixel.sum += w * color;
+                    pixel.weight += w;
+                }
+            }
+        }
+    }
+
+    /// The filtered radiance of pixel `(i, j)`.
+    pub fn pixel(&self, i: u32, j: u32) -> Color {
+        let pixel = self.pixels[(j * self.width + i) as usize];
+        // The negative lobes of filters such as Mitchell's can cancel out
+        // the weight of sparse samples, leaving nothing to divide by.
+        if pixel.weight > MIN_WEIGHT {
+            pixel.sum / pixel.weight
+        } else {
+            BLACK
+        }
+    }
+
+    /// The filtered radiance of every pixel as RGB floats, row by row from
+    /// the top of the image. Nothing is clamped.
+    pub fn to_rgb32f(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity((3 * self.width * self.height) as usize);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let c = self.pixel(i, j);
+                data.extend([c.x, c.y, c.z]);
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::WHITE;
+
+    fn mitchell() -> Filter {
+        Filter::new("mitchell", None).unwrap()
+    }
+
+    #[test]
+    fn negative_weights_resolve_to_black() {
+        let mut film = Film::new(4, 1, mitchell());
+        // Only the negative lobe of pixel 0 reaches this sample.
+        film.add_sample(2.0, 0.5, WHITE);
+        assert_eq!(film.pixel(0, 0), BLACK);
+        assert!(film.pixel(1, 0).x > 0.0);
+    }
+
+    #[test]
+    fn cancelling_weights_resolve_to_black() {
+        let filter = mitchell();
+        let negative = filter.evaluate(1.5, 0.0);
+        assert!(negative < 0.0);
+        // The offset from pixel 0 whose weight cancels the negative one.
+        let (mut lo, mut hi) = (0.5, 1.5);
+        for _ in 0..40 {
+            let mid = 0.5 * (lo + hi);
+            if filter.evaluate(mid, 0.0) + negative > 0.0 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let mut film = Film::new(1, 1, filter);
+        film.add_sample(0.5 - 1.5, 0.5, WHITE);
+        film.add_sample(0.5 + lo, 0.5, 0.5 * WHITE);
+        let c = film.pixel(0, 0);
+        assert!(c.is_finite(), "{:?}", c);
+        assert_eq!(c, BLACK);
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+
+/// Pixel reconstruction filter. Each sample is weighted by the filter centred
+/// on every pixel within `radius` of it, measured in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, sigma: f32 },
+    Mitchell { radius: f32, b: f32, c: f32 },
+}
+
+impl Filter {
+    /// The filter called `name`, with its usual radius unless one is given.
+    pub fn new(name: &str, radius: Option<f32>) -> Result<Self> {
+        let filter = match name {
+            "box" => Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            },
+            "tent" => Filter::Tent {
+                radius: radius.unwrap_or(1.0),
+            },
+            "gaussian" => {
+                let radius = radius.unwrap_or(1.5);
+                Filter::Gaussian {
+                    radius,
+                    sigma: radius / 3.0,
+                }
+            }
+            "mitchell" => Filter::Mitchell {
+                radius: radius.unwrap_or(2.0),
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            },
+            _ => return Err(anyhow!("unknown filter {}", name)),
+        };
+        Ok(filter)
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius } => radius,
+            Filter::Tent { radius } => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    /// Weight of a sample `(x, y)` pixels away from a pixel centre.
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        match *self {
+            Filter::Box { radius } => {
+                if x.abs() <= radius && y.abs() <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - x.abs()).max(0.0) * (radius - y.abs()).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let g = |v: f32| {
+                    let edge = gaussian(radius, sigma);
+                    (gaussian(v, sigma) - edge).max(0.0)
+                };
+                g(x) * g(y)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                mitchell(2.0 * x / radius, b, c) * mitchell(2.0 * y / radius, b, c)
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+/// Mitchell-Netravali cubic on `[-2, 2]`.
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+    if x <= 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x <= 2.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
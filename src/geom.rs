@@ -1,7 +1,7 @@
 pub use glam::*;
 use rand::Rng;
 
-pub const INFINITY: f32 = std::f32::MAX;
+pub const INFINITY: f32 = f32::MAX;
 pub const PI: f32 = std::f32::consts::PI;
 
 pub type Point3 = Vec3;
@@ -38,13 +38,17 @@ impl Ray {
     }
 
     pub fn transform(&self, mat: Mat4) -> Self {
-        let mut r = self.clone();
+        let mut r = *self;
         r.origin = mat.transform_point3(r.origin);
         r.direction = mat.transform_vector3(r.direction);
         r
     }
 }
 
+pub fn luminance(c: Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
 pub fn dot(v: Vec3, w: Vec3) -> f32 {
     v.dot(w)
 }
@@ -64,6 +68,38 @@ pub fn refract(v: Vec3, n: Vec3, eta_ratio: f32) -> Vec3 {
     eta_ratio * (uv - n * dt) - n * discriminant.sqrt()
 }
 
+/// Fraction of unpolarized light reflected by a dielectric interface at
+/// `cos_i` from the normal, with `eta_ratio` the index of refraction on the
+/// incident side over that on the other, 1 under total internal reflection.
+pub fn fresnel(cos_i: f32, eta_ratio: f32) -> f32 {
+    let sin2_t = eta_ratio * eta_ratio * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta_ratio * cos_i - cos_t) / (eta_ratio * cos_i + cos_t);
+    let r_p = (cos_i - eta_ratio * cos_t) / (cos_i + eta_ratio * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+/// Fraction of unpolarized light reflected at `cos_i` from the normal by a
+/// conductor with complex index of refraction `eta + i k`, for one
+/// wavelength.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    0.5 * (r_s + r_p)
+}
+
 pub fn rand_in_cube<R: Rng>(rng: &mut R) -> Vec3 {
     let x: f32 = rng.gen_range(-1.0..1.0);
     let y: f32 = rng.gen_range(-1.0..1.0);
@@ -88,17 +124,30 @@ pub fn random_unit_vector<R: Rng>(rng: &mut R) -> Vec3 {
 }
 
 pub fn random_in_unit_disk<R: Rng>(rng: &mut R) -> Vec3 {
-    loop {
-        let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-        if p.length() < 1.0 {
-            return p;
-        }
+    concentric_disk(vec2(rng.gen(), rng.gen())).extend(0.0)
+}
+
+/// Maps a point of the unit square to the unit disk, keeping areas in
+/// proportion (Shirley and Chiu's concentric mapping).
+pub fn concentric_disk(u: Vec2) -> Vec2 {
+    let p = 2.0 * u - Vec2::ONE;
+    if p == Vec2::ZERO {
+        return p;
     }
+    let (r, theta) = if p.x.abs() > p.y.abs() {
+        (p.x, PI / 4.0 * (p.y / p.x))
+    } else {
+        (p.y, PI / 2.0 - PI / 4.0 * (p.x / p.y))
+    };
+    r * vec2(theta.cos(), theta.sin())
 }
 
 pub fn random_cosine_direction<R: Rng>(rng: &mut R) -> Vec3 {
-    let r1: f32 = rng.gen();
-    let r2: f32 = rng.gen();
+    cosine_direction(rng.gen(), rng.gen())
+}
+
+/// Maps a point of the unit square to a cosine distributed direction around +z.
+pub fn cosine_direction(r1: f32, r2: f32) -> Vec3 {
     let z = (1.0 - r2).sqrt();
 
     let phi = 2.0 * PI * r1;
@@ -201,6 +250,11 @@ impl Onb {
         a.x * self.u + a.y * self.v + a.z * self.w
     }
 
+    /// Coordinates of `a` in this basis, the inverse of `local`.
+    pub fn project(&self, a: Vec3) -> Vec3 {
+        vec3(dot(a, self.u), dot(a, self.v), dot(a, self.w))
+    }
+
     pub fn build_from_w(n: Vec3) -> Self {
         let w = n.normalize();
         let a = if w.x.abs() > 0.9 {
@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+/// Candela distribution of a luminaire from an IES LM-63 file, with type C
+/// photometry: vertical angles from the nadir, which is the light's axis,
+/// and horizontal angles around it. Intensities are divided by the peak, so
+/// a light scales its colour by them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, increasing.
+    pub vertical: Vec<f32>,
+    /// Horizontal angles in degrees, increasing. One angle means the
+    /// distribution is the same all around, a last angle of 90 or 180
+    /// means the rest follows by symmetry, and a later one means they cover
+    /// the full turn, wrapping around to the first.
+    pub horizontal: Vec<f32>,
+    /// Relative intensity for each horizontal angle, then each vertical one.
+    pub intensity: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        // Keywords such as [MANUFAC] come before the TILT line.
+        let tilt = loop {
+            let line = lines
+                .next()
+                .ok_or_else(|| anyhow!("IES file has no TILT line"))?;
+            if let Some(tilt) = line.trim().strip_prefix("TILT=") {
+                break tilt.trim().to_string();
+            }
+        };
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_ascii_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| anyhow!("bad number {} in IES file", token))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(anyhow!("IES file ends early")))
+        };
+        match tilt.as_str() {
+            "NONE" => {}
+            "INCLUDE" => {
+                // Lamp to luminaire geometry, then the tilt angles and their
+                // factors, which only matter for lamps mounted at an angle.
+                next()?;
+                let pairs = next()? as usize;
+                for _ in 0..2 * pairs {
+                    next()?;
+                }
+            }
+            _ => return Err(anyhow!("IES tilt files are not supported: {}", tilt)),
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        if photometric_type != 1.0 {
+            return Err(anyhow!(
+                "only type C IES photometry is supported, not type {}",
+                photometric_type
+            ));
+        }
+        // Units, the luminous opening's size, ballast factor, a reserved
+        // field and input watts.
+        for _ in 0..7 {
+            next()?;
+        }
+        let vertical = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>>>()?;
+        let horizontal = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>>>()?;
+        let candela = (0..vertical_count * horizontal_count)
+            .map(|_| next().map(|c| c * multiplier))
+            .collect::<Result<Vec<_>>>()?;
+        if vertical.is_empty() || horizontal.is_empty() {
+            return Err(anyhow!("IES file has no angles"));
+        }
+        let peak = candela.iter().cloned().fold(0.0, f32::max);
+        if peak <= 0.0 {
+            return Err(anyhow!("IES file has no light"));
+        }
+        Ok(Self {
+            vertical,
+            horizontal,
+            intensity: candela.iter().map(|c| c / peak).collect(),
+        })
+    }
+
+    /// Relative intensity at `theta` degrees from the nadir and `phi` degrees
+    /// around it, interpolated between the measured angles and zero outside
+    /// the vertical ones.
+    pub fn intensity(&self, theta: f32, phi: f32) -> f32 {
+        let phi = phi.rem_euclid(360.0);
+        let phi = match self.horizontal.last() {
+            Some(&last) if last <= 90.0 => {
+                let phi = if phi > 180.0 { 360.0 - phi } else { phi };
+                if phi > 90.0 {
+                    180.0 - phi
+                } else {
+                    phi
+                }
+            }
+            Some(&last) if last <= 180.0 && phi > 180.0 => 360.0 - phi,
+            _ => phi,
+        };
+        let (v0, v1, tv) = match bracket(&self.vertical, theta) {
+            Some(b) => b,
+            None => return 0.0,
+        };
+        let first = self.horizontal[0];
+        let last = self.horizontal.len() - 1;
+        let (h0, h1, th) = match bracket(&self.horizontal, phi) {
+            Some(b) => b,
+            // A full turn wraps around from the last angle to the first.
+            None if self.horizontal[last] > 180.0 => {
+                let span = first + 360.0 - self.horizontal[last];
+                let phi = if phi < first { phi + 360.0 } else { phi };
+                let t = if span > 0.0 {
+                    (phi - self.horizontal[last]) / span
+                } else {
+                    0.0
+                };
+                (last, 0, t)
+            }
+            None if phi < first => (0, 0, 0.0),
+            None => (last, last, 0.0),
+        };
+        let n = self.vertical.len();
+        let at = |h: usize, v: usize| self.intensity[h * n + v];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(at(h0, v0), at(h0, v1), tv),
+            lerp(at(h1, v0), at(h1, v1), tv),
+            th,
+        )
+    }
+}
+
+/// Indices of the angles either side of `x` and how far it is between them,
+/// or None outside `angles`.
+fn bracket(angles: &[f32], x: f32) -> Option<(usize, usize, f32)> {
+    let last = angles.len() - 1;
+    if x < angles[0] || x > angles[last] {
+        return None;
+    }
+    if last == 0 {
+        return Some((0, 0, 0.0));
+    }
+    let i = angles[1..].partition_point(|&a| a < x).min(last - 1);
+    let span = angles[i + 1] - angles[i];
+    let t = if span > 0.0 {
+        (x - angles[i]) / span
+    } else {
+        0.0
+    };
+    Some((i, i + 1, t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small file with a tilt section, bilateral symmetry and a
+    /// multiplier of 2.
+    const BILATERAL: &str = "IESNA:LM-63-2002
+[TEST] small
+TILT=INCLUDE
+1
+2
+0 90
+1 0.5
+1 1000 2 3 3 1 1 0 0 0
+1 1 50
+0 45 90
+0 90 180
+100 50 0
+80 40 0
+60 30 0
+";
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn parses_past_the_tilt_section() {
+        let profile = IesProfile::parse(BILATERAL).unwrap();
+        assert_eq!(profile.vertical, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.horizontal, vec![0.0, 90.0, 180.0]);
+        assert!(close(profile.intensity(0.0, 0.0), 1.0));
+        assert!(close(profile.intensity(45.0, 90.0), 0.4));
+        assert!(close(profile.intensity(22.5, 0.0), 0.75));
+        assert_eq!(profile.intensity(100.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn multiplier_cancels_out_of_relative_intensities() {
+        let profile = IesProfile::parse(BILATERAL).unwrap();
+        let unscaled = IesProfile::parse(&BILATERAL.replace("1 1000 2", "1 1000 1")).unwrap();
+        assert_eq!(profile, unscaled);
+    }
+
+    #[test]
+    fn folds_symmetric_profiles() {
+        let profile = IesProfile::parse(BILATERAL).unwrap();
+        assert!(close(profile.intensity(45.0, 270.0), 0.4));
+        assert!(close(profile.intensity(45.0, 225.0), 0.35));
+
+        let quadrant = BILATERAL
+            .replace("3 3 1 1 0 0 0", "3 2 1 1 0 0 0")
+            .replace("0 90 180\n", "0 90\n")
+            .replace("60 30 0\n", "");
+        let profile = IesProfile::parse(&quadrant).unwrap();
+        assert_eq!(profile.horizontal, vec![0.0, 90.0]);
+        for phi in [30.0, 150.0, 210.0, 330.0] {
+            assert!(close(profile.intensity(0.0, phi), 1.0 - 0.2 / 3.0));
+        }
+    }
+
+    #[test]
+    fn full_turn_wraps_to_the_first_angle() {
+        let full = BILATERAL
+            .replace("3 3 1 1 0 0 0", "3 4 1 1 0 0 0")
+            .replace("0 90 180\n", "0 90 180 270\n")
+            .replace("60 30 0\n", "60 30 0\n40 20 0\n");
+        let profile = IesProfile::parse(&full).unwrap();
+        assert!(close(profile.intensity(0.0, 270.0), 0.4));
+        assert!(close(profile.intensity(0.0, 315.0), 0.7));
+        assert!(close(profile.intensity(0.0, -45.0), 0.7));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::geom::{dot, Color, Ray, BLACK};
+use crate::render::Integrator;
+use crate::sampler::Sampler;
+use crate::scene::World;
+
+/// Whitted style ray tracer with the OpenGL lighting model of the course:
+/// ambient and emission plus each light reflected by the material's BSDF,
+/// which for the course's colours is the diffuse and Phong terms, and rays
+/// traced along every delta lobe, such as the mirror reflection weighted by
+/// the specular colour. Glossy lobes only reflect lights.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlIntegrator;
+
+/// Depth traced when the scene leaves it unbounded, which only the path
+/// tracer's Russian roulette can cope with.
+const UNBOUNDED_DEPTH: i32 = 5;
+
+impl GlIntegrator {
+    pub(crate) fn trace(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        depth: i32,
+    ) -> Color {
+        let max_depth = if world.max_depth < 0 {
+            UNBOUNDED_DEPTH
+        } else {
+            world.max_depth
+        };
+        if depth >= max_depth {
+            return BLACK;
+        }
+        if let Some(rec) = world.hit(ray, 0.001, f32::MAX) {
+            let material = &rec.material;
+            let bsdf = material.bsdf(world.shading);
+            let mut color = material.ambient + material.emission;
+            let wo = -ray.direction.normalize();
+            for light in &world.lights {
+                let samples = light.samples();
+                let mut direct = BLACK;
+                for _ in 0..samples {
+                    if let Some(l) = light.illuminate(rec.point, world, sampler) {
+                        direct += bsdf.direct(rec.normal, wo, &l);
+                    }
+                }
+                color += direct / samples as f32;
+            }
+            for (direction, weight) in bsdf.delta_directions(rec.normal, wo) {
+                let scattered = Ray::new(rec.point, direction);
+                color += weight * self.trace(&scattered, world, sampler, depth + 1);
+            }
+            // A ray leaving through the surface crossed the inside.
+            if material.absorption != BLACK && dot(ray.direction, rec.normal) > 0.0 {
+                color *= material.transmittance(rec.t * ray.direction.length());
+            }
+            color
+        } else {
+            world.background(ray.direction)
+        }
+    }
+}
+
+impl Integrator for GlIntegrator {
+    type State = ();
+
+    fn thread_state(&self) -> Self::State {}
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        _state: &mut (),
+    ) -> Color {
+        self.trace(ray, world, sampler, 0)
+    }
+}
@@ -0,0 +1,181 @@
+use crate::geom::{dot, Color, Ray, BLACK, WHITE};
+use crate::render::Integrator;
+use crate::sampler::Sampler;
+use crate::scene::World;
+
+/// Monte Carlo path tracer. The first hit adds its ambient, and every hit adds
+/// its emission, which is how emissive surfaces light the scene, plus the
+/// direct light sampled from each light. The surfaces of area lights and the
+/// environment add their emission only where light sampling cannot have seen
+/// them: to camera rays and after mirror bounces. Since the mirror bounce is
+/// what reflects them, only lights that no ray can hit get the course's
+/// highlight. Light colours are scaled as in `GlIntegrator` so that direct
+/// lighting agrees with it: a Lambertian surface with BRDF `diffuse / PI`
+/// facing a light of colour `c` reflects `diffuse * c`. The path then continues
+/// in a direction sampled from the material's BSDF, from a lobe picked in
+/// proportion to its weight: for the course's colours a cosine distributed
+/// diffuse bounce or the mirror direction, picked by the luminance of `diffuse`
+/// and `specular`, or for dielectrics the Fresnel reflected or refracted
+/// direction. It ends when it escapes, reaches `max_depth`, unless that is
+/// negative, or is ended by Russian roulette.
+#[derive(Debug, Clone, Copy)]
+pub struct PathIntegrator {
+    /// Bounces after which paths may be ended by Russian roulette.
+    pub rr_depth: i32,
+}
+
+impl Default for PathIntegrator {
+    fn default() -> Self {
+        Self { rr_depth: 3 }
+    }
+}
+
+impl Integrator for PathIntegrator {
+    type State = ();
+
+    fn thread_state(&self) -> Self::State {}
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        _state: &mut (),
+    ) -> Color {
+        let mut radiance = BLACK;
+        let mut throughput = WHITE;
+        let mut ray = *ray;
+        let mut depth = 0;
+        let mut specular_bounce = false;
+        while world.max_depth < 0 || depth < world.max_depth {
+            let rec = match world.hit(&ray, 0.001, f32::MAX) {
+                Some(rec) => rec,
+                None => {
+                    // Like area lights, the environment is sampled directly.
+                    if depth == 0 || specular_bounce {
+                        radiance += throughput * world.background(ray.direction);
+                    }
+                    break;
+                }
+            };
+            let material = &rec.material;
+            // A ray leaving through the surface crossed the inside.
+            if material.absorption != BLACK && dot(ray.direction, rec.normal) > 0.0 {
+                throughput *= material.transmittance(rec.t * ray.direction.length());
+            }
+            // Ambient stands in for all indirect light, which later bounces
+            // gather themselves.
+            if depth == 0 {
+                radiance += throughput * material.ambient;
+            }
+            // Area lights are already counted by sampling them directly,
+            // except where only a mirror bounce can find them.
+            if !material.emitter || depth == 0 || specular_bounce {
+                radiance += throughput * material.emission;
+            }
+
+            let bsdf = material.bsdf(world.shading);
+            let wo = -ray.direction.normalize();
+            // Surfaces that only reflect are lit from whichever side the ray
+            // hits, while transmission needs to know which side is inside.
+            let normal = if dot(rec.normal, wo) < 0.0 && !bsdf.transmits() {
+                -rec.normal
+            } else {
+                rec.normal
+            };
+            for light in &world.lights {
+                let samples = light.samples();
+                let mut direct = BLACK;
+                for _ in 0..samples {
+                    if let Some(mut l) = light.illuminate(rec.point, world, sampler) {
+                        if !light.is_delta() {
+                            l.highlight = BLACK;
+                        }
+                        direct += bsdf.direct(normal, wo, &l);
+                    }
+                }
+                radiance += throughput * direct / samples as f32;
+            }
+
+            // Draw every dimension up front so each bounce uses the same ones.
+            let u_lobe = sampler.get_1d();
+            let u_direction = sampler.get_2d();
+            let u_roulette = sampler.get_1d();
+
+            let scattered = match bsdf.sample(normal, wo, u_lobe, u_direction) {
+                Some(scattered) => scattered,
+                None => break,
+            };
+            ray = Ray::new(rec.point, scattered.wi);
+            throughput *= scattered.weight;
+            specular_bounce = scattered.delta;
+
+            depth += 1;
+            if depth >= self.rr_depth {
+                let q = (1.0 - throughput.max_element()).max(0.05);
+                if u_roulette < q {
+                    break;
+                }
+                throughput /= 1.0 - q;
+            }
+        }
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edsl::Edsl;
+    use crate::environment::Environment;
+    use crate::geom::{point3, vec3, Vec2, PI};
+    use crate::sampler::SamplerKind;
+
+    /// Mean red radiance along `n` rays, each made from a sample by `ray`.
+    fn mean_radiance(world: &World, n: u32, ray: impl Fn(Vec2) -> Ray) -> f32 {
+        let mut sampler = SamplerKind::Random.create(n, 7);
+        let mut total = 0.0;
+        for s in 0..n {
+            sampler.start_pixel_sample(0, 0, s);
+            let ray = ray(sampler.get_2d());
+            total += PathIntegrator::default()
+                .radiance(&ray, world, sampler.as_mut(), &mut ())
+                .x;
+        }
+        total / n as f32
+    }
+
+    #[test]
+    fn unbounded_paths_converge_inside_a_glowing_sphere() {
+        // Each bounce reflects half and adds 0.5, so emission sums to 1,
+        // and the ambient is added once.
+        let mut edsl = Edsl::default();
+        edsl.max_depth(-1);
+        edsl.ambient(0.2, 0.2, 0.2);
+        edsl.diffuse(0.5, 0.5, 0.5);
+        edsl.specular(0.0, 0.0, 0.0);
+        edsl.emission(0.5, 0.5, 0.5);
+        edsl.sphere(0.0, 0.0, 0.0, 1.0);
+        let radiance = mean_radiance(&edsl.run(), 20000, |u| {
+            let z = 1.0 - 2.0 * u.x;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.y;
+            Ray::new(point3(0.0, 0.0, 0.0), vec3(r * phi.cos(), r * phi.sin(), z))
+        });
+        assert!((radiance - 1.2).abs() < 0.02, "{}", radiance);
+    }
+
+    #[test]
+    fn a_white_furnace_reflects_the_albedo() {
+        // A convex sphere sees only the uniform environment, once.
+        let mut edsl = Edsl::default();
+        edsl.environment(Environment::new(vec![WHITE; 8], 4, 2, 1.0, 0.0));
+        edsl.diffuse(0.5, 0.5, 0.5);
+        edsl.specular(0.0, 0.0, 0.0);
+        edsl.sphere(0.0, 0.0, 0.0, 1.0);
+        let radiance = mean_radiance(&edsl.run(), 4000, |_| {
+            Ray::new(point3(0.0, 0.0, 3.0), vec3(0.0, 0.0, -1.0))
+        });
+        assert!((radiance - 0.5).abs() < 0.02, "{}", radiance);
+    }
+}
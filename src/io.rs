@@ -1,54 +1,271 @@
+use crate::display::DisplayTransform;
+use crate::film::Film;
+use anyhow::{anyhow, Result};
+use exr::prelude::f16;
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{ImageBuffer, Rgb};
 use png::{BitDepth, ColorType, Encoder};
 use std::fs::File;
-use std::io::BufWriter;
 use std::io::Write;
-use std::path::PathBuf;
-
-pub fn write_ppm(data: &[u8], width: u32, height: u32, name: &'static str) {
-    let path = format!(r"images/{}", name);
-    let mut num = 0;
-    let mut sketch = PathBuf::from(format!(r"{}_{}", path, num));
-    sketch.set_extension("ppm");
-    while sketch.exists() {
-        num += 1;
-        sketch = PathBuf::from(format!(r"{}_{}", path, num));
-        sketch.set_extension("ppm");
-    }
-    let mut file = File::create(&sketch).unwrap();
-    writeln!(file, "P3").unwrap();
-    writeln!(file, "{} {}", width, height).unwrap();
-    writeln!(file, "255").unwrap();
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Channel type of OpenEXR files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+impl FromStr for ExrPrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "half" => Ok(ExrPrecision::Half),
+            "float" => Ok(ExrPrecision::Float),
+            _ => Err(anyhow!("unknown exr precision {}", s)),
+        }
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+/// Writes `film` to `path`. pfm, hdr and exr files keep the radiance as it
+/// is, and other formats get the output of `display` at its bit depth.
+pub fn write_film(
+    film: &Film,
+    path: impl AsRef<Path>,
+    display: &DisplayTransform,
+    precision: ExrPrecision,
+) -> Result<()> {
+    let path = path.as_ref();
+    let (width, height) = (film.width, film.height);
+    match extension(path).as_deref() {
+        Some("pfm") => write_pfm(&film.to_rgb32f(), width, height, path),
+        Some("hdr") => write_hdr(&film.to_rgb32f(), width, height, path),
+        Some("exr") => write_exr(&film.to_rgb32f(), width, height, path, precision),
+        _ if display.bit_depth == 16 => write_image16(&display.to_rgb16(film), width, height, path),
+        _ => write_image(&display.to_rgb8(film), width, height, path),
+    }
+}
+
+/// Writes 8 bit RGB `data` to `path` in the format given by its extension:
+/// png, ppm, or anything else the image crate can encode, such as jpg.
+pub fn write_image(data: &[u8], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("png") => write_png(data, width, height, path),
+        Some("ppm") => write_ppm(data, width, height, path),
+        Some(_) => {
+            image::save_buffer(path, data, width, height, image::ColorType::Rgb8)?;
+            Ok(())
+        }
+        None => Err(anyhow!("no image format for {}", path.display())),
+    }
+}
+
+/// Writes 16 bit RGB `data` to `path` like `write_image`, for formats that
+/// have 16 bit channels.
+pub fn write_image16(data: &[u16], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("png") => write_png16(data, width, height, path),
+        Some("ppm") => write_ppm16(data, width, height, path),
+        Some(_) => {
+            let image = ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data.to_vec())
+                .ok_or_else(|| anyhow!("expected {}x{} RGB pixels", width, height))?;
+            image.save(path)?;
+            Ok(())
+        }
+        None => Err(anyhow!("no image format for {}", path.display())),
+    }
+}
+
+pub fn write_ppm(data: &[u8], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    ppm(data, 255, width, height, path.as_ref())
+}
+
+pub fn write_ppm16(data: &[u16], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    ppm(data, 65535, width, height, path.as_ref())
+}
+
+fn ppm<T: std::fmt::Display>(
+    data: &[T],
+    max: u32,
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "P3")?;
+    writeln!(file, "{} {}", width, height)?;
+    writeln!(file, "{}", max)?;
     for y in 0..height {
         for x in 0..width {
             let offset = ((y * width * 3) + x * 3) as usize;
             write!(
                 &mut file,
                 "{} {} {} ",
-                data[offset + 0],
+                data[offset],
                 data[offset + 1],
                 data[offset + 2]
-            )
-            .unwrap()
+            )?
         }
-        write!(&mut file, "\n").unwrap()
+        writeln!(&mut file)?
     }
+    file.flush()?;
+    Ok(())
 }
 
-pub fn write_png(data: &[u8], width: u32, height: u32, name: &'static str) {
-    let path = format!(r"images/{}", name);
-    let mut num = 0;
-    let mut sketch = PathBuf::from(format!(r"{}_{}", path, num));
-    sketch.set_extension("png");
-    while sketch.exists() {
-        num += 1;
-        sketch = PathBuf::from(format!(r"{}_{}", path, num));
-        sketch.set_extension("png");
-    }
-    let file = File::create(&sketch).unwrap();
+pub fn write_png(data: &[u8], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    png(data, BitDepth::Eight, width, height, path.as_ref())
+}
+
+pub fn write_png16(data: &[u16], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_be_bytes()).collect();
+    png(&bytes, BitDepth::Sixteen, width, height, path.as_ref())
+}
+
+fn png(data: &[u8], depth: BitDepth, width: u32, height: u32, path: &Path) -> Result<()> {
+    let file = File::create(path)?;
     let w = &mut BufWriter::new(file);
     let mut encoder = Encoder::new(w, width, height);
     encoder.set_color(ColorType::Rgb);
-    encoder.set_depth(BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(data).unwrap();
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    Ok(())
+}
+
+/// Writes RGB floats as a little endian portable float map, which stores its
+/// rows from the bottom of the image up.
+pub fn write_pfm(data: &[f32], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    let row = 3 * width as usize;
+    for y in (0..height as usize).rev() {
+        for v in &data[y * row..(y + 1) * row] {
+            file.write_all(&v.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes RGB floats as a Radiance RGBE image.
+pub fn write_hdr(data: &[f32], width: u32, height: u32, path: impl AsRef<Path>) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let pixels: Vec<Rgb<f32>> = data
+        .chunks_exact(3)
+        .map(|c| Rgb([c[0].max(0.0), c[1].max(0.0), c[2].max(0.0)]))
+        .collect();
+    HdrEncoder::new(file).encode(&pixels, width as usize, height as usize)?;
+    Ok(())
+}
+
+/// Writes RGB floats as an OpenEXR image with half or float channels.
+pub fn write_exr(
+    data: &[f32],
+    width: u32,
+    height: u32,
+    path: impl AsRef<Path>,
+    precision: ExrPrecision,
+) -> Result<()> {
+    let (width, height) = (width as usize, height as usize);
+    let rgb = |x: usize, y: usize| {
+        let i = 3 * (y * width + x);
+        (data[i], data[i + 1], data[i + 2])
+    };
+    match precision {
+        ExrPrecision::Half => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let (r, g, b) = rgb(x, y);
+            (f16::from_f32(r), f16::from_f32(g), f16::from_f32(b))
+        })?,
+        ExrPrecision::Float => exr::prelude::write_rgb_file(path, width, height, rgb)?,
+    }
+    Ok(())
+}
+
+/// Reads the RGB floats of a pfm or hdr image, row by row from the top, with
+/// its width and height.
+pub fn read_radiance(path: impl AsRef<Path>) -> Result<(Vec<f32>, u32, u32)> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("pfm") => read_pfm(path),
+        Some("hdr") => read_hdr(path),
+        _ => Err(anyhow!("{} is not a pfm or hdr image", path.display())),
+    }
+}
+
+/// Reads a portable float map, colour or greyscale, of either byte order.
+pub fn read_pfm(path: impl AsRef<Path>) -> Result<(Vec<f32>, u32, u32)> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    // Four header fields, then a single whitespace character before the data.
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(anyhow!("{} has a short pfm header", path.display()));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    pos += 1;
+    let channels = match fields[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(anyhow!("{} is not a pfm image", path.display())),
+    };
+    let width = fields[1].parse::<u32>()?;
+    let height = fields[2].parse::<u32>()?;
+    let little_endian = fields[3].parse::<f32>()? < 0.0;
+    let count = (width * height) as usize * channels;
+    let body = bytes
+        .get(pos..pos + 4 * count)
+        .ok_or_else(|| anyhow!("{} has too little pfm data", path.display()))?;
+    let values: Vec<f32> = body
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect();
+    let row = width as usize * channels;
+    let mut data = Vec::with_capacity(3 * (width * height) as usize);
+    for y in (0..height as usize).rev() {
+        for pixel in values[y * row..(y + 1) * row].chunks_exact(channels) {
+            match *pixel {
+                [v] => data.extend([v, v, v]),
+                _ => data.extend_from_slice(pixel),
+            }
+        }
+    }
+    Ok((data, width, height))
+}
+
+/// Reads a Radiance RGBE image.
+pub fn read_hdr(path: impl AsRef<Path>) -> Result<(Vec<f32>, u32, u32)> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let data = pixels.iter().flat_map(|p| p.0).collect();
+    Ok((data, metadata.width, metadata.height))
 }
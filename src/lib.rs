@@ -1,13 +1,23 @@
 pub mod aabb;
+pub mod bsdf;
 pub mod bvh;
 pub mod camera;
+pub mod display;
+pub mod distribution;
 pub mod edsl;
+pub mod environment;
+pub mod film;
+pub mod filter;
 pub mod geom;
+pub mod ies;
+pub mod integrators;
 pub mod io;
 pub mod light;
 pub mod material;
 pub mod object;
 pub mod parse;
 pub mod render;
+pub mod sampler;
 pub mod scene;
 pub mod shapes;
+pub mod sky;
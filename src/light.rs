@@ -1,4 +1,55 @@
-#[derive(Debug, Clone, Copy)]
+use crate::environment::Environment;
+use crate::geom::{
+    concentric_disk, cross, degrees_to_radians, dot, point3, vec3, Color, Onb, Point3, Ray, Vec2,
+    Vec3, PI, WHITE,
+};
+use crate::ies::IesProfile;
+use crate::material::Material;
+use crate::object::Shape;
+use crate::sampler::Sampler;
+use crate::scene::World;
+use crate::shapes::disc::Disc;
+use crate::shapes::sphere::Sphere;
+use crate::shapes::triangle::Triangle;
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How points on an area light are picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LightSampling {
+    /// Uniformly over the light's area.
+    #[default]
+    Area,
+    /// Uniformly over the cone of directions subtending the light, or its
+    /// bounding sphere for quads and discs. Falls back to area sampling from
+    /// inside that sphere.
+    SolidAngle,
+}
+
+impl FromStr for LightSampling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "area" => Ok(LightSampling::Area),
+            "solidangle" => Ok(LightSampling::SolidAngle),
+            _ => Err(anyhow!("unknown light sampling {}", s)),
+        }
+    }
+}
+
+/// Point and directional lights follow the course's OpenGL model, with
+/// `x, y, z` the direction towards a directional light, and spot
+/// lights are point lights that shine along `direction`, at full strength
+/// within `inner` degrees of it and fading smoothly to nothing at `outer`
+/// degrees, scaled by an IES profile if they have one. Area lights
+/// emit `radiance` from one side of a quad `corner + s * edge1 + t * edge2`,
+/// facing along `edge1 x edge2`, from one side of a disc, or from a sphere,
+/// and take `samples` shadow rays at each shading point, as do environment
+/// lights and suns, which shine from a disc of `radius` degrees around
+/// `direction`.
+#[derive(Debug, Clone)]
 pub enum Light {
     Directional {
         x: f32,
@@ -16,4 +67,443 @@ pub enum Light {
         g: f32,
         b: f32,
     },
-}
\ No newline at end of file
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        inner: f32,
+        outer: f32,
+        color: Color,
+        /// Where the profile's horizontal angles start, projected
+        /// perpendicular to `direction`. They increase counterclockwise
+        /// seen from behind the light.
+        up: Vec3,
+        profile: Option<Arc<IesProfile>>,
+    },
+    Quad {
+        corner: Point3,
+        edge1: Vec3,
+        edge2: Vec3,
+        radiance: Color,
+        samples: u32,
+        sampling: LightSampling,
+    },
+    Disc {
+        center: Point3,
+        normal: Vec3,
+        radius: f32,
+        radiance: Color,
+        samples: u32,
+        sampling: LightSampling,
+    },
+    Sphere {
+        center: Point3,
+        radius: f32,
+        radiance: Color,
+        samples: u32,
+        sampling: LightSampling,
+    },
+    Environment {
+        map: Arc<Environment>,
+        samples: u32,
+    },
+    Sun {
+        direction: Vec3,
+        radius: f32,
+        radiance: Color,
+        samples: u32,
+    },
+}
+
+/// Light arriving at a point from one sample of a light.
+#[derive(Debug, Clone, Copy)]
+pub struct Illumination {
+    /// Unit direction towards the light.
+    pub direction: Vec3,
+    /// Colour of the light in the units of the OpenGL model, where a surface
+    /// of diffuse colour `kd` facing the light reflects `kd * color`. For area
+    /// lights this is the emitted radiance times the sampled solid angle over
+    /// PI, so a Lambertian surface reflects the right amount.
+    pub color: Color,
+    /// Scale of the course's specular highlight, which is `color` unless an
+    /// integrator already reflects the light some other way. Directional,
+    /// point and spot lights cap their colour at 1 here, as the course's
+    /// reference images do, though point and spot lights still attenuate it
+    /// with distance.
+    pub highlight: Color,
+}
+
+impl Light {
+    /// Shadow rays taken towards the light at each shading point.
+    pub fn samples(&self) -> u32 {
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } | Light::Spot { .. } => 1,
+            Light::Quad { samples, .. }
+            | Light::Disc { samples, .. }
+            | Light::Sphere { samples, .. }
+            | Light::Environment { samples, .. }
+            | Light::Sun { samples, .. } => samples.max(1),
+        }
+    }
+
+    /// Whether the light sits at a point or direction that no ray can hit,
+    /// so that it is only ever reached by sampling it.
+    pub fn is_delta(&self) -> bool {
+        matches!(
+            self,
+            Light::Directional { .. } | Light::Point { .. } | Light::Spot { .. }
+        )
+    }
+
+    /// The surface of an area light, emitting its radiance so that camera
+    /// and reflected rays see it.
+    pub fn shapes(&self) -> Vec<Shape> {
+        let material = |radiance: Color| {
+            Arc::new(Material {
+                emission: radiance,
+                emitter: true,
+                ..Material::default()
+            })
+        };
+        match *self {
+            Light::Directional { .. }
+            | Light::Point { .. }
+            | Light::Spot { .. }
+            | Light::Environment { .. }
+            | Light::Sun { .. } => Vec::new(),
+            Light::Quad {
+                corner,
+                edge1,
+                edge2,
+                radiance,
+                ..
+            } => {
+                let m = material(radiance);
+                let (a, b, c, d) = (
+                    corner,
+                    corner + edge1,
+                    corner + edge1 + edge2,
+                    corner + edge2,
+                );
+                vec![
+                    Shape::Triangle(Triangle::new(a, b, c, m.clone(), Default::default())),
+                    Shape::Triangle(Triangle::new(a, c, d, m, Default::default())),
+                ]
+            }
+            Light::Disc {
+                center,
+                normal,
+                radius,
+                radiance,
+                ..
+            } => vec![Shape::Disc(Disc::new(
+                center,
+                normal,
+                radius,
+                material(radiance),
+            ))],
+            Light::Sphere {
+                center,
+                radius,
+                radiance,
+                ..
+            } => vec![Shape::Sphere(Sphere::new(
+                center,
+                radius,
+                material(radiance),
+                Default::default(),
+            ))],
+        }
+    }
+
+    /// Light arriving at `point` from one sample of the light, or None when
+    /// something blocks it or it faces away. Area lights take their sample
+    /// from `sampler`.
+    pub fn illuminate(
+        &self,
+        point: Point3,
+        world: &World,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Illumination> {
+        match self {
+            &Light::Directional { x, y, z, r, g, b } => {
+                let light_direction = vec3(x, y, z).normalize();
+                let light_ray = Ray::new(point, light_direction);
+                if world.occluded(&light_ray, 0.001, f32::MAX) {
+                    return None;
+                }
+                Some(Illumination {
+                    direction: light_direction,
+                    color: Color::new(r, g, b),
+                    highlight: highlight(Color::new(r, g, b)),
+                })
+            }
+            &Light::Point { x, y, z, r, g, b } => {
+                let light_vector = point3(x, y, z) - point;
+                let light_direction = light_vector.normalize();
+                let light_ray = Ray::new(point, light_direction);
+                if world.occluded(&light_ray, 0.001, light_vector.length()) {
+                    return None;
+                }
+                let [c, l, q] = world.attenuation;
+                let a = c + l * light_vector.length() + q * light_vector.length_squared();
+                Some(Illumination {
+                    direction: light_direction,
+                    color: Color::new(r, g, b) / a,
+                    highlight: highlight(Color::new(r, g, b)) / a,
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                inner,
+                outer,
+                color,
+                up,
+                profile,
+            } => {
+                let light_vector = *position - point;
+                let light_direction = light_vector.normalize();
+                let axis = direction.normalize();
+                let cos_theta = dot(-light_direction, axis);
+                let mut strength = falloff(
+                    cos_theta,
+                    degrees_to_radians(*outer).cos(),
+                    degrees_to_radians(*inner).cos(),
+                );
+                if let Some(profile) = profile {
+                    let u = (*up - axis * dot(*up, axis))
+                        .try_normalize()
+                        .unwrap_or_else(|| Onb::build_from_w(axis).u);
+                    let v = cross(u, axis);
+                    let d = -light_direction;
+                    let theta = cos_theta.clamp(-1.0, 1.0).acos();
+                    let phi = dot(d, v).atan2(dot(d, u));
+                    strength *= profile.intensity(theta.to_degrees(), phi.to_degrees());
+                }
+                if strength <= 0.0 {
+                    return None;
+                }
+                let light_ray = Ray::new(point, light_direction);
+                if world.occluded(&light_ray, 0.001, light_vector.length()) {
+                    return None;
+                }
+                let [c, l, q] = world.attenuation;
+                let a = c + l * light_vector.length() + q * light_vector.length_squared();
+                Some(Illumination {
+                    direction: light_direction,
+                    color: *color * strength / a,
+                    highlight: highlight(*color) * strength / a,
+                })
+            }
+            Light::Sun {
+                direction,
+                radius,
+                radiance,
+                ..
+            } => {
+                let cos_max = degrees_to_radians(*radius).cos();
+                let direction = cone_direction(*direction, cos_max, sampler.get_2d());
+                let light_ray = Ray::new(point, direction);
+                if world.occluded(&light_ray, 0.001, f32::MAX) {
+                    return None;
+                }
+                let color = *radiance * 2.0 * (1.0 - cos_max);
+                Some(Illumination {
+                    direction,
+                    color,
+                    highlight: color,
+                })
+            }
+            Light::Environment { map, .. } => {
+                let (direction, radiance, pdf) = map.sample(sampler.get_2d())?;
+                let light_ray = Ray::new(point, direction);
+                if world.occluded(&light_ray, 0.001, f32::MAX) {
+                    return None;
+                }
+                let color = radiance / (PI * pdf);
+                Some(Illumination {
+                    direction,
+                    color,
+                    highlight: color,
+                })
+            }
+            _ => {
+                let (target, color) = self.sample(point, sampler)?;
+                let light_vector = target - point;
+                let distance = light_vector.length();
+                let direction = light_vector / distance;
+                let light_ray = Ray::new(point, direction);
+                if world.occluded(&light_ray, 0.001, distance * (1.0 - 1e-4)) {
+                    return None;
+                }
+                Some(Illumination {
+                    direction,
+                    color,
+                    highlight: color,
+                })
+            }
+        }
+    }
+
+    /// A point on an area light seen from `point` and the colour it
+    /// contributes, before the shadow test.
+    fn sample(&self, point: Point3, sampler: &mut dyn Sampler) -> Option<(Point3, Color)> {
+        let u = sampler.get_2d();
+        match *self {
+            Light::Directional { .. }
+            | Light::Point { .. }
+            | Light::Spot { .. }
+            | Light::Environment { .. }
+            | Light::Sun { .. } => None,
+            Light::Quad {
+                corner,
+                edge1,
+                edge2,
+                radiance,
+                sampling,
+                ..
+            } => {
+                let n = cross(edge1, edge2);
+                let area = n.length();
+                let normal = n / area;
+                let center = corner + 0.5 * (edge1 + edge2);
+                let bound = 0.5 * (edge1 + edge2).length().max((edge1 - edge2).length());
+                if sampling == LightSampling::SolidAngle {
+                    if let Some((direction, weight)) = sample_cone(point, center, bound, u) {
+                        let p = hit_plane(point, direction, center, normal)?;
+                        // Coordinates of p along the edges, from the dual basis.
+                        let d = p - corner;
+                        let s = dot(d, cross(edge2, normal)) / area;
+                        let t = dot(d, cross(normal, edge1)) / area;
+                        if !(0.0..=1.0).contains(&s) || !(0.0..=1.0).contains(&t) {
+                            return None;
+                        }
+                        return facing(point, p, normal).then(|| (p, radiance * weight));
+                    }
+                }
+                let p = corner + u.x * edge1 + u.y * edge2;
+                area_sample(point, p, normal, area, radiance)
+            }
+            Light::Disc {
+                center,
+                normal,
+                radius,
+                radiance,
+                sampling,
+                ..
+            } => {
+                let normal = normal.normalize();
+                if sampling == LightSampling::SolidAngle {
+                    if let Some((direction, weight)) = sample_cone(point, center, radius, u) {
+                        let p = hit_plane(point, direction, center, normal)?;
+                        if (p - center).length_squared() > radius * radius {
+                            return None;
+                        }
+                        return facing(point, p, normal).then(|| (p, radiance * weight));
+                    }
+                }
+                let onb = Onb::build_from_w(normal);
+                let q = radius * concentric_disk(u);
+                let p = center + q.x * onb.u + q.y * onb.v;
+                area_sample(point, p, normal, PI * radius * radius, radiance)
+            }
+            Light::Sphere {
+                center,
+                radius,
+                radiance,
+                sampling,
+                ..
+            } => {
+                if sampling == LightSampling::SolidAngle {
+                    if let Some((direction, weight)) = sample_cone(point, center, radius, u) {
+                        // Nearest intersection, or the tangent point when
+                        // rounding misses the sphere at the cone's edge.
+                        let oc = point - center;
+                        let b = dot(oc, direction);
+                        let c = oc.length_squared() - radius * radius;
+                        let t = -b - (b * b - c).max(0.0).sqrt();
+                        return Some((point + t * direction, radiance * weight));
+                    }
+                }
+                let z = 1.0 - 2.0 * u.x;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.y;
+                let normal = vec3(r * phi.cos(), r * phi.sin(), z);
+                let p = center + radius * normal;
+                area_sample(point, p, normal, 4.0 * PI * radius * radius, radiance)
+            }
+        }
+    }
+}
+
+/// The colour of a directional, point or spot light's highlight.
+fn highlight(color: Color) -> Color {
+    color.min(WHITE)
+}
+
+/// 0 at or outside the cone with cosine `cos_outer`, 1 inside the one with
+/// `cos_inner`, and smoothstep in between.
+fn falloff(cos_theta: f32, cos_outer: f32, cos_inner: f32) -> f32 {
+    if cos_theta >= cos_inner {
+        1.0
+    } else if cos_theta <= cos_outer {
+        0.0
+    } else {
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Colour of a point `p` picked uniformly from a light of the given `area`:
+/// radiance times the solid angle one sample stands for, over PI.
+fn area_sample(
+    point: Point3,
+    p: Point3,
+    normal: Vec3,
+    area: f32,
+    radiance: Color,
+) -> Option<(Point3, Color)> {
+    let d = p - point;
+    let distance_squared = d.length_squared();
+    let cos_light = dot(normal, -d) / distance_squared.sqrt();
+    if cos_light <= 0.0 {
+        return None;
+    }
+    Some((p, radiance * cos_light * area / (PI * distance_squared)))
+}
+
+/// A direction picked uniformly in the cone from `point` around the sphere
+/// at `center`, and the cone's solid angle over PI, or None from inside the
+/// sphere.
+fn sample_cone(point: Point3, center: Point3, radius: f32, u: Vec2) -> Option<(Vec3, f32)> {
+    let axis = center - point;
+    let sin2_max = radius * radius / axis.length_squared();
+    if sin2_max >= 1.0 {
+        return None;
+    }
+    let cos_max = (1.0 - sin2_max).sqrt();
+    Some((cone_direction(axis, cos_max, u), 2.0 * (1.0 - cos_max)))
+}
+
+/// A direction picked uniformly within the cone of `cos_max` around `axis`.
+fn cone_direction(axis: Vec3, cos_max: f32, u: Vec2) -> Vec3 {
+    let cos_theta = 1.0 - u.x * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    let local = vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+    Onb::build_from_w(axis).local(local)
+}
+
+fn hit_plane(point: Point3, direction: Vec3, center: Point3, normal: Vec3) -> Option<Point3> {
+    let denom = dot(normal, direction);
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    let t = dot(center - point, normal) / denom;
+    (t > 0.0).then(|| point + t * direction)
+}
+
+/// Whether the emitting side of a one sided light at `p` faces `point`.
+fn facing(point: Point3, p: Point3, normal: Vec3) -> bool {
+    dot(normal, point - p) > 0.0
+}
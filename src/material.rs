@@ -1,32 +1,139 @@
-use crate::geom::Color;
-
-#[derive(Debug, Clone, Copy)]
+use crate::bsdf::{Bsdf, Lobe};
+use crate::geom::{dot, reflect, Color, Vec3, BLACK};
+use std::str::FromStr;
 
+/// Course style material. A non-zero `transmission` makes it a dielectric
+/// with index of refraction `ior`, which reflects and refracts by the
+/// Fresnel equations in place of the mirror reflection weighted by
+/// `specular`, and lets through `transmission` of the refracted light.
+/// Light travelling inside it is absorbed at the rate `absorption` per unit
+/// length. Non-empty `lobes` take the place of the course's colours in how
+/// the surface scatters light. `ambient` is added wherever the surface is
+/// seen, as a stand-in for indirect light.
+#[derive(Debug, Clone)]
 pub struct Material {
+    pub ambient: Color,
     pub diffuse: Color,
     pub specular: Color,
     pub shininess: f32,
     pub emission: Color,
+    /// Whether this is the surface of an area light, whose emission is
+    /// sampled directly as well.
+    pub emitter: bool,
+    pub ior: f32,
+    pub transmission: Color,
+    pub absorption: Color,
+    pub lobes: Vec<Lobe>,
 }
 
 impl Material {
     pub fn new(diffuse: Color, specular: Color, shininess: f32, emission: Color) -> Self {
         Self {
+            ambient: BLACK,
             diffuse,
             specular,
             shininess,
             emission,
+            emitter: false,
+            ior: 1.0,
+            transmission: BLACK,
+            absorption: BLACK,
+            lobes: Vec::new(),
         }
     }
+
+    pub fn is_dielectric(&self) -> bool {
+        self.transmission != BLACK
+    }
+
+    /// How the surface scatters light, with highlights in the given shading
+    /// model. The course's colours are a Lambertian lobe for `diffuse` and a
+    /// highlight and mirror for `specular`, or a dielectric in place of the
+    /// mirror.
+    pub fn bsdf(&self, shading: ShadingModel) -> Bsdf<'_> {
+        if !self.lobes.is_empty() {
+            return Bsdf::new(&self.lobes);
+        }
+        // The mirror comes first so that paths pick it for the same samples
+        // as before there were lobes.
+        let mirror = if self.is_dielectric() {
+            Some(Lobe::Dielectric {
+                ior: self.ior,
+                transmission: self.transmission,
+                roughness: 0.0,
+            })
+        } else if self.specular != BLACK {
+            Some(Lobe::Mirror {
+                reflectance: self.specular,
+            })
+        } else {
+            None
+        };
+        let diffuse = (self.diffuse != BLACK).then_some(Lobe::Lambertian {
+            albedo: self.diffuse,
+        });
+        let highlight = (self.specular != BLACK).then_some(Lobe::Highlight {
+            specular: self.specular,
+            shininess: self.shininess,
+            model: shading,
+        });
+        Bsdf::inline([mirror, diffuse, highlight])
+    }
+
+    /// Fraction of light left after travelling `distance` inside.
+    pub fn transmittance(&self, distance: f32) -> Color {
+        let a = self.absorption * distance;
+        Color::new((-a.x).exp(), (-a.y).exp(), (-a.z).exp())
+    }
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
+            ambient: Color::new(0.0, 0.0, 0.0),
             diffuse: Color::new(0.0, 0.0, 0.0),
             specular: Color::new(0.0, 0.0, 0.0),
             shininess: 0.0,
             emission: Color::new(0.0, 0.0, 0.0),
+            emitter: false,
+            ior: 1.0,
+            transmission: Color::new(0.0, 0.0, 0.0),
+            absorption: Color::new(0.0, 0.0, 0.0),
+            lobes: Vec::new(),
+        }
+    }
+}
+
+/// Specular highlight of the OpenGL style lighting model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// `(n . h)^s` with `h` halfway between the light and eye directions.
+    #[default]
+    BlinnPhong,
+    /// `(r . v)^s` with `r` the light direction mirrored about the normal.
+    Phong,
+}
+
+impl ShadingModel {
+    /// Highlight for light arriving from `wi` seen from `wo`, both unit
+    /// vectors pointing away from the surface.
+    pub fn highlight(self, normal: Vec3, wi: Vec3, wo: Vec3, shininess: f32) -> f32 {
+        let c = match self {
+            ShadingModel::BlinnPhong => dot(normal, (wi + wo).normalize()),
+            ShadingModel::Phong => dot(reflect(-wi, normal), wo),
+        };
+        c.max(0.0).powf(shininess)
+    }
+}
+
+impl FromStr for ShadingModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blinn" => Ok(ShadingModel::BlinnPhong),
+            "phong" => Ok(ShadingModel::Phong),
+            _ => Err(anyhow::anyhow!("unknown shading model {}", s)),
         }
     }
 }
@@ -1,6 +1,7 @@
 use crate::aabb::{surrounding_box, Aabb};
 use crate::geom::{Point3, Ray, Vec3};
 use crate::material::Material;
+use crate::shapes::disc::Disc;
 use crate::shapes::sphere::Sphere;
 use crate::shapes::triangle::Triangle;
 use std::ops::Index;
@@ -29,6 +30,7 @@ impl Hit {
 pub enum Shape {
     Sphere(Sphere),
     Triangle(Triangle),
+    Disc(Disc),
 }
 
 impl Shape {
@@ -36,6 +38,7 @@ impl Shape {
         match self {
             Shape::Sphere(s) => s.hit(ray, t_min, t_max),
             Shape::Triangle(t) => t.hit(ray, t_min, t_max),
+            Shape::Disc(d) => d.hit(ray, t_min, t_max),
         }
     }
 
@@ -43,6 +46,7 @@ impl Shape {
         match self {
             Shape::Sphere(s) => s.bounding_box,
             Shape::Triangle(t) => t.bounding_box,
+            Shape::Disc(d) => d.bounding_box,
         }
     }
 }
@@ -53,7 +57,7 @@ impl Default for Shape {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Objects(pub Vec<Shape>);
 
 impl Objects {
@@ -80,6 +84,10 @@ impl Objects {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl Index<usize> for Objects {
@@ -89,9 +97,3 @@ impl Index<usize> for Objects {
         &self.0[index]
     }
 }
-
-impl Default for Objects {
-    fn default() -> Self {
-        Self(Vec::new())
-    }
-}
@@ -1,32 +1,56 @@
-use crate::bvh::Node;
-use crate::camera::Camera;
+use crate::bsdf::{Fresnel, Lobe, Metal};
+use crate::bvh::{Builder, Bvh};
+use crate::camera::{Camera, Lens, Projection};
+use crate::display::{DisplayTransform, ToneMap};
+use crate::environment::Environment;
+use crate::filter::Filter;
 use crate::geom::*;
-use crate::light::Light;
-use crate::material::Material;
+use crate::ies::IesProfile;
+use crate::light::{Light, LightSampling};
+use crate::material::{Material, ShadingModel};
 use crate::object::{Objects, Shape};
-use crate::scene::World;
+use crate::sampler::SamplerKind;
+use crate::scene::{View, World};
 use crate::shapes::sphere::Sphere;
 use crate::shapes::triangle::Triangle;
+use crate::sky::Sky;
 use anyhow::{anyhow, Result};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
+/// Reads the scene at `path`. Images are written to the file named by the
+/// `output` command, or to the scene's name with a png extension. Unlike IES
+/// files and environment maps, which are found next to the scene, these are
+/// relative to the working directory, as the course's renderers write them.
+pub fn parse_scene(path: PathBuf) -> Result<World> {
     let mut w = 0.0;
     let mut h = 0.0;
-    let mut ambient = BLACK;
     let mut objects = Objects(Vec::new());
     let mut lights = Vec::new();
-    let mut camera = Camera::default();
-    let mut material = Material::default();
+    let mut cameras: Vec<(String, Camera)> = Vec::new();
+    let mut lens = Lens::default();
+    // Shapes share the material until a material command changes it.
+    let mut material = Arc::new(Material::default());
     let mut _maxverts = 0;
     let mut vertices = Vec::new();
     let mut transforms: Vec<Mat4> = vec![Mat4::IDENTITY];
     let mut attenuation = [1.0, 0.0, 0.0];
     let mut max_depth = 5;
+    let mut shading = ShadingModel::default();
+    let mut builder = Builder::default();
+    let mut samples = 1;
+    let mut sampler = SamplerKind::default();
+    let mut filter = Filter::default();
+    let mut seed = 0;
+    let mut output = None;
+    let mut display = DisplayTransform::default();
+    let mut light_samples = 1;
+    let mut environment = None;
+    let mut sun_radius = 0.27;
+    let mut light_sampling = LightSampling::default();
 
-    let scene = fs::read_to_string(path)?;
+    let scene = fs::read_to_string(&path)?;
     let lines = scene.lines();
     for line in lines {
         let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
@@ -34,6 +58,15 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
             continue;
         };
         match tokens[0] {
+            "output" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "output command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                output = Some(PathBuf::from(tokens[1]));
+            }
             "size" => {
                 if tokens.len() != 3 {
                     return Err(anyhow!(
@@ -53,27 +86,198 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 };
                 max_depth = tokens[1].parse::<i32>()?;
             }
+            "shading" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "shading command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                shading = tokens[1].parse::<ShadingModel>()?;
+            }
+            "samples" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "samples command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                samples = tokens[1].parse::<u32>()?;
+            }
+            "sampler" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "sampler command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                sampler = tokens[1].parse::<SamplerKind>()?;
+            }
+            "filter" => {
+                if tokens.len() != 2 && tokens.len() != 3 {
+                    return Err(anyhow!(
+                        "filter command requires 1 or 2 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let radius = match tokens.get(2) {
+                    Some(r) => Some(r.parse::<f32>()?),
+                    None => None,
+                };
+                filter = Filter::new(tokens[1], radius)?;
+            }
+            "exposure" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "exposure command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                display.exposure = tokens[1].parse::<f32>()?;
+            }
+            "tonemap" => {
+                if tokens.len() != 2 && tokens.len() != 3 {
+                    return Err(anyhow!(
+                        "tonemap command requires 1 or 2 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let white = match tokens.get(2) {
+                    Some(w) => Some(w.parse::<f32>()?),
+                    None => None,
+                };
+                display.tone_map = ToneMap::new(tokens[1], white)?;
+            }
+            "encoding" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "encoding command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                display.encoding = tokens[1].parse()?;
+            }
+            "dither" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "dither command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                display.dither = match tokens[1] {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(anyhow!("dither must be on or off, not {}", tokens[1])),
+                };
+            }
+            "bitdepth" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "bitdepth command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                display.bit_depth = match tokens[1] {
+                    "8" => 8,
+                    "16" => 16,
+                    _ => return Err(anyhow!("bitdepth must be 8 or 16, not {}", tokens[1])),
+                };
+            }
+            "seed" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "seed command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                seed = tokens[1].parse::<u64>()?;
+            }
+            "bvh" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "bvh command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                builder = tokens[1].parse::<Builder>()?;
+            }
             "camera" => {
-                if tokens.len() != 11 {
+                // A projection may come first, with ortho taking the view
+                // height and fisheye the field of view in place of fovy, and
+                // equirect neither.
+                let (kind, args) = match tokens.get(1).copied() {
+                    Some(kind @ ("ortho" | "fisheye" | "equirect")) => (kind, &tokens[2..]),
+                    _ => ("perspective", &tokens[1..]),
+                };
+                let required = if kind == "equirect" { 9 } else { 10 };
+                if args.len() != required && args.len() != required + 1 {
                     return Err(anyhow!(
-                        "camera command requires 10 arguments, not {}",
-                        tokens.len() - 1,
+                        "{} camera command requires {} or {} arguments, not {}",
+                        kind,
+                        required,
+                        required + 1,
+                        args.len(),
                     ));
                 };
-                let from_x = tokens[1].parse::<f32>()?;
-                let from_y = tokens[2].parse::<f32>()?;
-                let from_z = tokens[3].parse::<f32>()?;
-                let at_x = tokens[4].parse::<f32>()?;
-                let at_y = tokens[5].parse::<f32>()?;
-                let at_z = tokens[6].parse::<f32>()?;
-                let up_x = tokens[7].parse::<f32>()?;
-                let up_y = tokens[8].parse::<f32>()?;
-                let up_z = tokens[9].parse::<f32>()?;
-                let fov = tokens[10].parse::<f32>()?;
+                let from_x = args[0].parse::<f32>()?;
+                let from_y = args[1].parse::<f32>()?;
+                let from_z = args[2].parse::<f32>()?;
+                let at_x = args[3].parse::<f32>()?;
+                let at_y = args[4].parse::<f32>()?;
+                let at_z = args[5].parse::<f32>()?;
+                let up_x = args[6].parse::<f32>()?;
+                let up_y = args[7].parse::<f32>()?;
+                let up_z = args[8].parse::<f32>()?;
+                let projection = match kind {
+                    "ortho" => Projection::Orthographic {
+                        height: args[9].parse::<f32>()?,
+                    },
+                    "fisheye" => Projection::Fisheye {
+                        fov: args[9].parse::<f32>()?,
+                    },
+                    "equirect" => Projection::Equirectangular,
+                    _ => Projection::Perspective {
+                        fovy: args[9].parse::<f32>()?,
+                    },
+                };
                 let look_from = point3(from_x, from_y, from_z);
                 let look_at = point3(at_x, at_y, at_z);
                 let up = point3(up_x, up_y, up_z);
-                camera = Camera::new(w, h, look_from, look_at, up, fov);
+                let name = match args.get(required) {
+                    Some(name) => name.to_string(),
+                    None => format!("camera{}", cameras.len() + 1),
+                };
+                if cameras.iter().any(|(n, _)| *n == name) {
+                    return Err(anyhow!("there is already a camera named {}", name));
+                }
+                let camera =
+                    Camera::with_view(w, h, look_from, look_at, up, projection).with_lens(lens);
+                cameras.push((name, camera));
+            }
+            // Like the material commands, aperture and focus only apply to
+            // what follows them, so they go before the cameras they set up.
+            "aperture" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "aperture command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                lens.aperture = tokens[1].parse::<f32>()?;
+            }
+            "focus" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "focus command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                if tokens[1] == "auto" {
+                    lens.autofocus = true;
+                } else {
+                    lens.focus = Some(tokens[1].parse::<f32>()?);
+                    lens.autofocus = false;
+                }
             }
             "ambient" => {
                 if tokens.len() != 4 {
@@ -85,7 +289,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let r = tokens[1].parse::<f32>()?;
                 let g = tokens[2].parse::<f32>()?;
                 let b = tokens[3].parse::<f32>()?;
-                ambient = Color::new(r, g, b);
+                Arc::make_mut(&mut material).ambient = Color::new(r, g, b);
             }
             "directional" => {
                 if tokens.len() != 7 {
@@ -117,6 +321,192 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let b = tokens[6].parse::<f32>()?;
                 lights.push(Light::Point { x, y, z, r, g, b });
             }
+            "spot" => {
+                if tokens.len() != 12 && tokens.len() != 16 {
+                    return Err(anyhow!(
+                        "spot command requires 11 or 15 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let v: Vec<f32> = tokens[1..12]
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let direction = vec3(v[3], v[4], v[5]);
+                // IES files are found next to the scene, and followed by the
+                // direction of their 0 degree horizontal angle.
+                let (profile, up) = match tokens.get(12) {
+                    Some(file) => {
+                        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                        let up = vec3(
+                            tokens[13].parse::<f32>()?,
+                            tokens[14].parse::<f32>()?,
+                            tokens[15].parse::<f32>()?,
+                        );
+                        if cross(up, direction).length_squared() == 0.0 {
+                            return Err(anyhow!(
+                                "spot up vector must not be parallel to its direction"
+                            ));
+                        }
+                        (Some(Arc::new(IesProfile::load(dir.join(file))?)), up)
+                    }
+                    None => (None, Vec3::ZERO),
+                };
+                lights.push(Light::Spot {
+                    position: point3(v[0], v[1], v[2]),
+                    direction,
+                    inner: v[6],
+                    outer: v[7],
+                    color: Color::new(v[8], v[9], v[10]),
+                    up,
+                    profile,
+                });
+            }
+            "environment" => {
+                if !(2..=4).contains(&tokens.len()) {
+                    return Err(anyhow!(
+                        "environment command requires 1 to 3 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let intensity = match tokens.get(2) {
+                    Some(t) => t.parse::<f32>()?,
+                    None => 1.0,
+                };
+                let rotation = match tokens.get(3) {
+                    Some(t) => t.parse::<f32>()?,
+                    None => 0.0,
+                };
+                // Like IES files, maps are found next to the scene.
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                let map = Arc::new(Environment::load(dir.join(tokens[1]), intensity, rotation)?);
+                lights.retain(|l| !matches!(l, Light::Environment { .. }));
+                lights.push(Light::Environment {
+                    map: map.clone(),
+                    samples: light_samples,
+                });
+                environment = Some(map);
+            }
+            "sky" => {
+                if tokens.len() != 8 && tokens.len() != 9 {
+                    return Err(anyhow!(
+                        "sky command requires 7 or 8 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let v: Vec<f32> = tokens[1..]
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let intensity = v.get(7).copied().unwrap_or(1.0 / 32.0);
+                let s = Sky::new(
+                    vec3(v[0], v[1], v[2]),
+                    v[3],
+                    Color::new(v[4], v[5], v[6]),
+                    intensity,
+                );
+                let map = Arc::new(s.environment(512, 256));
+                lights.retain(|l| !matches!(l, Light::Environment { .. } | Light::Sun { .. }));
+                lights.push(Light::Environment {
+                    map: map.clone(),
+                    samples: light_samples,
+                });
+                lights.push(s.sun(sun_radius, light_samples));
+                environment = Some(map);
+            }
+            // Like lightsamples, sunradius only reaches the skies after it.
+            "sunradius" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "sunradius command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                sun_radius = tokens[1].parse::<f32>()?;
+            }
+            "quadlight" => {
+                if tokens.len() != 13 {
+                    return Err(anyhow!(
+                        "quadlight command requires 12 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let v: Vec<f32> = tokens[1..]
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let light = Light::Quad {
+                    corner: point3(v[0], v[1], v[2]),
+                    edge1: vec3(v[3], v[4], v[5]),
+                    edge2: vec3(v[6], v[7], v[8]),
+                    radiance: Color::new(v[9], v[10], v[11]),
+                    samples: light_samples,
+                    sampling: light_sampling,
+                };
+                objects.0.extend(light.shapes());
+                lights.push(light);
+            }
+            "disclight" => {
+                if tokens.len() != 11 {
+                    return Err(anyhow!(
+                        "disclight command requires 10 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let v: Vec<f32> = tokens[1..]
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let light = Light::Disc {
+                    center: point3(v[0], v[1], v[2]),
+                    normal: vec3(v[3], v[4], v[5]).normalize(),
+                    radius: v[6],
+                    radiance: Color::new(v[7], v[8], v[9]),
+                    samples: light_samples,
+                    sampling: light_sampling,
+                };
+                objects.0.extend(light.shapes());
+                lights.push(light);
+            }
+            "spherelight" => {
+                if tokens.len() != 8 {
+                    return Err(anyhow!(
+                        "spherelight command requires 7 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let v: Vec<f32> = tokens[1..]
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let light = Light::Sphere {
+                    center: point3(v[0], v[1], v[2]),
+                    radius: v[3],
+                    radiance: Color::new(v[4], v[5], v[6]),
+                    samples: light_samples,
+                    sampling: light_sampling,
+                };
+                objects.0.extend(light.shapes());
+                lights.push(light);
+            }
+            "lightsamples" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "lightsamples command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                light_samples = tokens[1].parse::<u32>()?;
+            }
+            "lightsampling" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "lightsampling command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                light_sampling = tokens[1].parse()?;
+            }
             "attenuation" => {
                 if tokens.len() != 4 {
                     return Err(anyhow!(
@@ -139,7 +529,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let r = tokens[1].parse::<f32>()?;
                 let g = tokens[2].parse::<f32>()?;
                 let b = tokens[3].parse::<f32>()?;
-                material.diffuse = Color::new(r, g, b);
+                Arc::make_mut(&mut material).diffuse = Color::new(r, g, b);
             }
             "specular" => {
                 if tokens.len() != 4 {
@@ -151,7 +541,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let r = tokens[1].parse::<f32>()?;
                 let g = tokens[2].parse::<f32>()?;
                 let b = tokens[3].parse::<f32>()?;
-                material.specular = Color::new(r, g, b);
+                Arc::make_mut(&mut material).specular = Color::new(r, g, b);
             }
             "shininess" => {
                 if tokens.len() != 2 {
@@ -161,7 +551,119 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                     ));
                 };
                 let s = tokens[1].parse::<f32>()?;
-                material.shininess = s;
+                Arc::make_mut(&mut material).shininess = s;
+            }
+            "ior" => {
+                if tokens.len() != 2 {
+                    return Err(anyhow!(
+                        "ior command requires 1 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                Arc::make_mut(&mut material).ior = tokens[1].parse::<f32>()?;
+            }
+            "transmission" => {
+                if tokens.len() != 4 {
+                    return Err(anyhow!(
+                        "transmission command requires 3 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let r = tokens[1].parse::<f32>()?;
+                let g = tokens[2].parse::<f32>()?;
+                let b = tokens[3].parse::<f32>()?;
+                Arc::make_mut(&mut material).transmission = Color::new(r, g, b);
+            }
+            "absorption" => {
+                if tokens.len() != 4 {
+                    return Err(anyhow!(
+                        "absorption command requires 3 arguments, not {}",
+                        tokens.len() - 1
+                    ));
+                };
+                let r = tokens[1].parse::<f32>()?;
+                let g = tokens[2].parse::<f32>()?;
+                let b = tokens[3].parse::<f32>()?;
+                Arc::make_mut(&mut material).absorption = Color::new(r, g, b);
+            }
+            "lobe" => {
+                if tokens.len() < 2 {
+                    return Err(anyhow!("lobe command requires a kind of lobe"));
+                };
+                let kind = tokens[1];
+                // Metals are named, with their numbers after the name.
+                let (metal, numbers) = match kind {
+                    "metal" if tokens.len() > 2 => {
+                        (Some(tokens[2].parse::<Metal>()?), &tokens[3..])
+                    }
+                    _ => (None, &tokens[2..]),
+                };
+                let v: Vec<f32> = numbers
+                    .iter()
+                    .map(|t| t.parse::<f32>())
+                    .collect::<Result<_, _>>()?;
+                let (counts, expected): (&[usize], &str) = match kind {
+                    "none" => (&[0], "0"),
+                    "coat" => (&[1], "1"),
+                    "lambertian" | "mirror" => (&[3], "3"),
+                    "phong" | "blinn" => (&[4], "4"),
+                    "conductor" => (&[4, 7], "4 or 7"),
+                    "metal" => (&[2], "2"),
+                    "dielectric" => (&[4, 5], "4 or 5"),
+                    kind => return Err(anyhow!("unknown lobe {}", kind)),
+                };
+                if !counts.contains(&(tokens.len() - 2)) {
+                    return Err(anyhow!(
+                        "lobe {} command requires {} arguments, not {}",
+                        kind,
+                        expected,
+                        tokens.len() - 2
+                    ));
+                };
+                let roughness = |i: usize| v.get(i).copied().unwrap_or(0.0);
+                let lobe = match kind {
+                    "none" => {
+                        Arc::make_mut(&mut material).lobes.clear();
+                        continue;
+                    }
+                    // The coat goes over every lobe given so far.
+                    "coat" => Lobe::Coated {
+                        ior: v[0],
+                        base: std::mem::take(&mut Arc::make_mut(&mut material).lobes),
+                    },
+                    "lambertian" => Lobe::Lambertian {
+                        albedo: Color::new(v[0], v[1], v[2]),
+                    },
+                    "mirror" => Lobe::Mirror {
+                        reflectance: Color::new(v[0], v[1], v[2]),
+                    },
+                    "phong" | "blinn" => Lobe::Phong {
+                        specular: Color::new(v[0], v[1], v[2]),
+                        exponent: v[3],
+                        model: kind.parse()?,
+                    },
+                    "conductor" if v.len() > 4 => Lobe::Conductor {
+                        fresnel: Fresnel::Conductor {
+                            eta: Color::new(v[0], v[1], v[2]),
+                            k: Color::new(v[3], v[4], v[5]),
+                        },
+                        roughness: roughness(6),
+                    },
+                    "conductor" => Lobe::Conductor {
+                        fresnel: Fresnel::Schlick(Color::new(v[0], v[1], v[2])),
+                        roughness: roughness(3),
+                    },
+                    "metal" => Lobe::Conductor {
+                        fresnel: metal.unwrap().fresnel(),
+                        roughness: roughness(0),
+                    },
+                    _ => Lobe::Dielectric {
+                        ior: v[0],
+                        transmission: Color::new(v[1], v[2], v[3]),
+                        roughness: roughness(4),
+                    },
+                };
+                Arc::make_mut(&mut material).lobes.push(lobe);
             }
             "emission" => {
                 if tokens.len() != 4 {
@@ -173,7 +675,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let r = tokens[1].parse::<f32>()?;
                 let g = tokens[2].parse::<f32>()?;
                 let b = tokens[3].parse::<f32>()?;
-                material.emission = Color::new(r, g, b);
+                Arc::make_mut(&mut material).emission = Color::new(r, g, b);
             }
             "maxverts" => {
                 if tokens.len() != 2 {
@@ -210,7 +712,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                     vertices[x],
                     vertices[y],
                     vertices[z],
-                    Arc::new(material.clone()),
+                    material.clone(),
                     *transforms.last().unwrap(),
                 );
                 objects.0.push(Shape::Triangle(triangle));
@@ -229,7 +731,7 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
                 let sphere = Sphere::new(
                     point3(x, y, z),
                     r,
-                    Arc::new(material.clone()),
+                    material.clone(),
                     *transforms.last().unwrap(),
                 );
                 objects.0.push(Shape::Sphere(sphere));
@@ -284,16 +786,28 @@ pub fn parse_scene<'a>(path: PathBuf) -> Result<World> {
             _ => continue,
         }
     }
-    let indices: Vec<usize> = (0..objects.len()).collect();
-    let nodes = Node::new(&objects, indices, 0);
+    let bvh = Bvh::new(&objects, builder);
+    let output = output.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or_default();
+        Path::new(stem).with_extension("png")
+    });
 
-    Ok(World {
-        camera,
-        bvh_node: nodes,
+    let mut world = World {
+        views: View::from_cameras(cameras, &output),
+        bvh,
         objects,
         lights,
-        ambient,
+        environment,
         attenuation,
         max_depth,
-    })
+        shading,
+        samples,
+        sampler,
+        filter,
+        seed,
+        display,
+        progress: false,
+    };
+    world.autofocus();
+    Ok(world)
 }
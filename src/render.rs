@@ -1,106 +1,218 @@
-use crate::geom::{dot, point3, reflect, vec3, Color, Ray, BLACK};
-use crate::light::Light;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::geom::{Color, Ray, BLACK};
+use crate::integrators::gl::GlIntegrator;
+use crate::sampler::Sampler;
 use crate::scene::World;
 use rayon::prelude::*;
+use std::sync::Mutex;
 
-type Integrator = fn(&Ray, &World, i32) -> Color;
+/// Computes the radiance arriving along camera rays. `setup` runs once before
+/// each render. Work running in parallel takes a `State` from a pool to use as
+/// scratch space, so `thread_state` is called about once per rayon worker and
+/// each state is reused for many pixels. Closures with the signature of
+/// `radiance`, minus the state, are integrators too.
+pub trait Integrator: Sync {
+    type State: Send;
 
-pub fn gl_integrator(ray: &Ray, world: &World, depth: i32) -> Color {
-    if depth >= world.max_depth {
-        return BLACK;
-    }
-    if let Some(rec) = world.objects.hit(ray, 0.001, f32::MAX) {
-        let mut color = world.ambient + rec.material.emission;
-        for light in &world.lights {
-            match light {
-                Light::Directional { x, y, z, r, g, b } => {
-                    let light_vector = -vec3(*x, *y, *z);
-                    let light_direction = light_vector.normalize();
-                    let light_ray = Ray::new(rec.point, light_direction);
-                    let h = ((ray.origin - rec.point) + light_vector).normalize();
-                    let hit = world.objects.hit(&light_ray, 0.001, f32::MAX);
-                    if hit.is_none() {
-                        color += Color::new(*r, *g, *b)
-                            * rec.material.diffuse
-                            * dot(rec.normal, light_direction).max(0.0)
-                            + rec.material.specular
-                                * dot(rec.normal, h).max(0.0).powf(rec.material.shininess);
-                    }
-                }
-                Light::Point { x, y, z, r, g, b } => {
-                    let light_position = point3(*x, *y, *z);
-                    let light_vector = light_position - rec.point;
-                    let light_direction = light_vector.normalize();
-                    let light_ray = Ray::new(rec.point, light_direction);
-                    let h = ((ray.origin - rec.point) + light_vector).normalize();
-                    let hit = world.objects.hit(&light_ray, 0.001, f32::MAX);
-                    if hit.is_none() || hit.unwrap().t > light_vector.length() {
-                        let [c, l, q] = world.attenuation;
-                        let a = c + l * light_vector.length() + q * light_vector.length_squared();
-                        color += (Color::new(*r, *g, *b)
-                            * rec.material.diffuse
-                            * dot(rec.normal, light_direction).max(0.0)
-                            + rec.material.specular
-                                * dot(rec.normal, h).max(0.0).powf(rec.material.shininess))
-                            / a;
-                    }
-                }
-            }
-        }
-        let reflected_ray = Ray::new(rec.point, reflect(ray.direction, rec.normal));
-        color += rec.material.specular * gl_integrator(&reflected_ray, world, depth + 1);
-        color
-    } else {
-        BLACK
+    fn setup(&mut self, _world: &World) {}
+
+    fn thread_state(&self) -> Self::State;
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        state: &mut Self::State,
+    ) -> Color;
+}
+
+impl<F> Integrator for F
+where
+    F: Fn(&Ray, &World, &mut dyn Sampler) -> Color + Sync,
+{
+    type State = ();
+
+    fn thread_state(&self) -> Self::State {}
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        _state: &mut (),
+    ) -> Color {
+        self(ray, world, sampler)
     }
 }
 
-fn write_color(data: &mut Vec<u8>, pixel_color: Color, samples_per_pixel: u32) {
-    let mut r = pixel_color.x;
-    let mut g = pixel_color.y;
-    let mut b = pixel_color.z;
+/// The Whitted integrator as a function of the ray and its depth.
+pub fn gl_integrator(ray: &Ray, world: &World, sampler: &mut dyn Sampler, depth: i32) -> Color {
+    GlIntegrator.trace(ray, world, sampler, depth)
+}
 
-    let scale = 1.0 / samples_per_pixel as f32;
-    // May want to gamma correct here.
-    r = scale * r;
-    g = scale * g;
-    b = scale * b;
+/// Renders the first view of `environment`.
+pub fn render<I: Integrator>(environment: &World, integrator: &mut I) -> Film {
+    render_view(environment, 0, integrator)
+}
 
-    data.push((255.999 * r) as u8);
-    data.push((255.999 * g) as u8);
-    data.push((255.999 * b) as u8);
+/// Renders `environment.views[view]`.
+pub fn render_view<I: Integrator>(environment: &World, view: usize, integrator: &mut I) -> Film {
+    integrator.setup(environment);
+    render_camera(environment, &environment.views[view].camera, &*integrator)
 }
 
-pub fn render(environment: &World, integrator: Integrator) -> Vec<u8> {
-    let mut data: Vec<u8> = Vec::new();
-    let w = environment.camera.width as u32;
-    let h = environment.camera.height as u32;
+/// Renders every view of `environment`, in order, with one integrator setup.
+pub fn render_all<I: Integrator>(environment: &World, integrator: &mut I) -> Vec<Film> {
+    integrator.setup(environment);
+    environment
+        .views
+        .iter()
+        .map(|view| render_camera(environment, &view.camera, &*integrator))
+        .collect()
+}
+
+fn render_camera<I: Integrator>(environment: &World, camera: &Camera, integrator: &I) -> Film {
+    let w = camera.width as u32;
+    let h = camera.height as u32;
+    let samples = environment.samples.max(1);
+    let mut film = Film::new(w, h, environment.filter);
+    let states = Mutex::new(Vec::new());
 
     for j in 0..h {
-        eprintln!("Scanlines remaining: {}", j + 1);
-        let scanline: Vec<Color> = (0..w)
+        if environment.progress {
+            eprintln!("Scanlines remaining: {}", h - j);
+        }
+        let scanline: Vec<(f32, f32, Color)> = (0..w)
             .into_par_iter()
-            .map(|i| {
-                let mut pixel_color = BLACK;
-                let r = environment.camera.get_ray(j as f32, i as f32);
-                let mut rc = integrator(&r, environment, 0);
-                if rc.x.is_nan() {
-                    rc.x = 0.0
-                };
-                if rc.y.is_nan() {
-                    rc.y = 0.0
-                };
-                if rc.z.is_nan() {
-                    rc.z = 0.0
-                };
-                pixel_color += rc;
-                pixel_color
-            })
+            .map_init(
+                || {
+                    let sampler = environment.sampler.create(samples, environment.seed);
+                    let state = Pooled::take(&states, || integrator.thread_state());
+                    (sampler, state)
+                },
+                |(sampler, state), i| {
+                    (0..samples)
+                        .map(|s| {
+                            sampler.start_pixel_sample(i, j, s);
+                            // A single sample goes through the pixel centre.
+                            let (dy, dx) = if samples == 1 {
+                                (0.5, 0.5)
+                            } else {
+                                let p = sampler.get_pixel_2d();
+                                (p.y, p.x)
+                            };
+                            let (x, y) = (i as f32 + dx, j as f32 + dy);
+                            let r = if camera.aperture > 0.0 {
+                                camera.lens_ray(y, x, sampler.get_2d())
+                            } else {
+                                camera.sample_ray(y, x)
+                            };
+                            let mut rc = match r {
+                                Some(r) => integrator.radiance(
+                                    &r,
+                                    environment,
+                                    sampler.as_mut(),
+                                    state.get(),
+                                ),
+                                None => BLACK,
+                            };
+                            if rc.x.is_nan() {
+                                rc.x = 0.0
+                            };
+                            if rc.y.is_nan() {
+                                rc.y = 0.0
+                            };
+                            if rc.z.is_nan() {
+                                rc.z = 0.0
+                            };
+                            (x, y, rc)
+                        })
+                        .collect::<Vec<_>>()
+                },
+            )
+            .flatten_iter()
             .collect();
 
-        for pixel_color in scanline {
-            write_color(&mut data, pixel_color, 1);
+        for (x, y, color) in scanline {
+            film.add_sample(x, y, color);
+        }
+    }
+    film
+}
+
+/// A value taken from a pool, which goes back to it when dropped.
+struct Pooled<'a, T> {
+    value: Option<T>,
+    pool: &'a Mutex<Vec<T>>,
+}
+
+impl<'a, T> Pooled<'a, T> {
+    /// A value from `pool`, or a new one from `create` if it is empty.
+    fn take(pool: &'a Mutex<Vec<T>>, create: impl FnOnce() -> T) -> Self {
+        let value = pool.lock().unwrap().pop().unwrap_or_else(create);
+        Self {
+            value: Some(value),
+            pool,
         }
     }
-    data
+
+    fn get(&mut self) -> &mut T {
+        self.value.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.pool.lock().unwrap().push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::edsl::Edsl;
+    use crate::geom::point3;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the states it hands out.
+    #[derive(Default)]
+    struct Counting {
+        states: AtomicUsize,
+    }
+
+    impl Integrator for Counting {
+        type State = u32;
+
+        fn thread_state(&self) -> u32 {
+            self.states.fetch_add(1, Ordering::Relaxed);
+            0
+        }
+
+        fn radiance(&self, _: &Ray, _: &World, _: &mut dyn Sampler, state: &mut u32) -> Color {
+            *state += 1;
+            BLACK
+        }
+    }
+
+    #[test]
+    fn states_are_reused_across_scanlines() {
+        let mut edsl = Edsl::default();
+        edsl.size(32.0, 64.0);
+        edsl.camera(
+            point3(0.0, 0.0, 1.0),
+            point3(0.0, 0.0, 0.0),
+            point3(0.0, 1.0, 0.0),
+            45.0,
+        );
+        let world = edsl.run();
+        let mut integrator = Counting::default();
+        render(&world, &mut integrator);
+        let states = integrator.states.into_inner();
+        assert!(states >= 1);
+        assert!(states <= rayon::current_num_threads(), "{}", states);
+    }
 }
@@ -0,0 +1,480 @@
+use crate::geom::{vec2, Vec2};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Source of the sample values used to render a pixel. The renderer calls
+/// `start_pixel_sample` before each sample of a pixel, and every `get_1d` or
+/// `get_2d` call after that consumes the next dimension(s) of that sample, so
+/// integrators should ask for values in the same order at every bounce.
+pub trait Sampler {
+    fn samples_per_pixel(&self) -> u32;
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> Vec2;
+
+    /// Position of the sample inside the pixel.
+    fn get_pixel_2d(&mut self) -> Vec2 {
+        self.get_2d()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplerKind {
+    #[default]
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SamplerKind::Random),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(anyhow::anyhow!("unknown sampler {}", s)),
+        }
+    }
+}
+
+impl SamplerKind {
+    pub fn create(self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+/// Uniform random values, independent across samples and dimensions.
+#[derive(Debug, Clone)]
+pub struct RandomSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    rng: SmallRng,
+}
+
+impl RandomSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = SmallRng::seed_from_u64(hash(x, y, index, self.seed));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        vec2(self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Jittered strata. Each dimension splits the unit interval (or square) into
+/// as many strata as there are samples per pixel and visits them in a
+/// different random order, so the dimensions stay uncorrelated.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+    rng: SmallRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        // The most square factorisation of the sample count.
+        let mut x_strata = (samples_per_pixel as f32).sqrt() as u32;
+        while !samples_per_pixel.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+        Self {
+            samples_per_pixel,
+            x_strata,
+            y_strata: samples_per_pixel / x_strata,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    fn stratum(&mut self) -> u32 {
+        let (x, y) = self.pixel;
+        let h = hash(x, y, self.dimension, self.seed);
+        self.dimension += 1;
+        permutation_element(self.index, self.samples_per_pixel, h as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = SmallRng::seed_from_u64(hash(x, y, index, self.seed));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        let delta: f32 = self.rng.gen();
+        ((stratum as f32 + delta) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let stratum = self.stratum();
+        self.dimension += 1;
+        let x = stratum % self.x_strata;
+        let y = stratum / self.x_strata;
+        let dx: f32 = self.rng.gen();
+        let dy: f32 = self.rng.gen();
+        vec2(
+            ((x as f32 + dx) / self.x_strata as f32).min(ONE_MINUS_EPSILON),
+            ((y as f32 + dy) / self.y_strata as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// The Halton sequence, with each pixel and dimension Owen scrambled by its
+/// own hash so neighbouring pixels don't repeat the same pattern. Dimensions
+/// past the table of prime bases take uniform random values, since reusing a
+/// base would correlate them with an earlier dimension.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&mut self) -> f32 {
+        let (x, y) = self.pixel;
+        let d = self.dimension;
+        self.dimension += 1;
+        let h = hash(x, y, d, self.seed);
+        match PRIMES.get(d as usize) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.index as u64, h),
+            None => to_unit((mix_bits(h ^ self.index as u64) >> 32) as u32),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let u = self.sample_dimension();
+        let v = self.sample_dimension();
+        vec2(u, v)
+    }
+}
+
+/// Owen scrambled Sobol points. Every 1D or 2D request uses the first one or
+/// two Sobol dimensions with a fresh scramble and sample order, which keeps
+/// each pair well stratified without tables for higher dimensions.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let (x, y) = self.pixel;
+        let h = hash(x, y, self.dimension, self.seed);
+        self.dimension += 1;
+        h
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let h = self.next_hash();
+        let i = permutation_element(self.index, self.samples_per_pixel, h as u32);
+        to_unit(owen_scramble(i.reverse_bits(), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let h = self.next_hash();
+        self.dimension += 1;
+        let i = permutation_element(self.index, self.samples_per_pixel, h as u32);
+        let g = mix_bits(h);
+        vec2(
+            to_unit(owen_scramble(i.reverse_bits(), (h >> 32) as u32)),
+            to_unit(owen_scramble(sobol_second_dimension(i), g as u32)),
+        )
+    }
+}
+
+fn to_unit(v: u32) -> f32 {
+    (v as f32 * 2.0f32.powi(-32)).min(ONE_MINUS_EPSILON)
+}
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(x: u32, y: u32, d: u32, seed: u64) -> u64 {
+    mix_bits(((x as u64) << 40 ^ (y as u64) << 20 ^ d as u64) ^ mix_bits(seed))
+}
+
+/// Element `i` of a random permutation of `0..l` chosen by `p` (Kensler,
+/// "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+/// Hash based nested uniform scramble of a 32 bit fixed point sample
+/// (Burley, "Practical Hash-based Owen Scrambling").
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/// The second Sobol dimension, whose generator matrix is Pascal's triangle mod 2.
+fn sobol_second_dimension(mut a: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut r = 0;
+    while a != 0 {
+        if a & 1 != 0 {
+            r ^= v;
+        }
+        a >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
+
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    // Stop once further digits can't change the f32 result.
+    while 1.0 - (base - 1) as f32 * inv_base_m < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed_digits) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (inv_base_m * reversed_digits as f32).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Random,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    /// The values of one pixel sample, past the Halton table's dimensions.
+    fn values(sampler: &mut dyn Sampler, x: u32, y: u32, index: u32) -> Vec<f32> {
+        sampler.start_pixel_sample(x, y, index);
+        let mut values = Vec::new();
+        for _ in 0..20 {
+            values.push(sampler.get_1d());
+            let v = sampler.get_2d();
+            values.extend([v.x, v.y]);
+        }
+        values
+    }
+
+    #[test]
+    fn same_seed_and_pixel_give_the_same_values() {
+        for kind in KINDS {
+            let mut a = kind.create(16, 5);
+            let mut b = kind.create(16, 5);
+            // Another pixel in between must not change what follows.
+            let first = values(a.as_mut(), 3, 7, 2);
+            values(a.as_mut(), 4, 7, 2);
+            assert_eq!(values(a.as_mut(), 3, 7, 2), first, "{:?}", kind);
+            assert_eq!(values(b.as_mut(), 3, 7, 2), first, "{:?}", kind);
+            assert!(first.iter().all(|v| (0.0..1.0).contains(v)), "{:?}", kind);
+            let mut c = kind.create(16, 6);
+            assert_ne!(values(c.as_mut(), 3, 7, 2), first, "{:?}", kind);
+        }
+    }
+
+    /// Checks that the pixel's 16 samples put one value in each of 16
+    /// intervals of every 1D dimension and one point in each cell of a 4 by 4
+    /// grid of every 2D one.
+    fn check_strata(kind: SamplerKind) {
+        let mut sampler = kind.create(16, 9);
+        let samples: Vec<Vec<f32>> = (0..16)
+            .map(|i| values(sampler.as_mut(), 10, 20, i))
+            .collect();
+        for d in 0..20 {
+            let mut strata: Vec<u32> = samples.iter().map(|s| (s[3 * d] * 16.0) as u32).collect();
+            strata.sort();
+            assert_eq!(strata, (0..16).collect::<Vec<_>>(), "{:?} 1D {}", kind, d);
+            let mut cells: Vec<u32> = samples
+                .iter()
+                .map(|s| (s[3 * d + 2] * 4.0) as u32 * 4 + (s[3 * d + 1] * 4.0) as u32)
+                .collect();
+            cells.sort();
+            assert_eq!(cells, (0..16).collect::<Vec<_>>(), "{:?} 2D {}", kind, d);
+        }
+    }
+
+    #[test]
+    fn stratified_fills_every_stratum() {
+        check_strata(SamplerKind::Stratified);
+    }
+
+    #[test]
+    fn sobol_fills_every_stratum() {
+        check_strata(SamplerKind::Sobol);
+    }
+
+    #[test]
+    fn halton_stays_uncorrelated_past_its_bases() {
+        let mut sampler = HaltonSampler::new(64, 1);
+        let dimensions = 2 * PRIMES.len();
+        let samples: Vec<Vec<f32>> = (0..64)
+            .map(|i| {
+                sampler.start_pixel_sample(0, 0, i);
+                (0..dimensions).map(|_| sampler.get_1d()).collect()
+            })
+            .collect();
+        // With repeated bases, dimension d + 32 would reorder dimension d's
+        // digits the same way for every sample.
+        for d in 0..PRIMES.len() {
+            let same = samples
+                .iter()
+                .filter(|s| (s[d] * 2.0) as u32 == (s[d + PRIMES.len()] * 2.0) as u32)
+                .count();
+            assert!((16..=48).contains(&same), "dimension {}: {}", d, same);
+        }
+    }
+}
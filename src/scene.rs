@@ -1,16 +1,138 @@
-use crate::bvh::Node;
+use crate::bvh::{Builder, Bvh};
 use crate::camera::Camera;
-use crate::geom::Color;
+use crate::display::DisplayTransform;
+use crate::environment::Environment;
+use crate::filter::Filter;
+use crate::geom::{degrees_to_radians, dot, Color, Ray, Vec3, BLACK};
 use crate::light::Light;
-use crate::object::Objects;
+use crate::material::ShadingModel;
+use crate::object::{Hit, Objects};
+use crate::sampler::SamplerKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A named camera and the image file it renders to.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub name: String,
+    pub camera: Camera,
+    pub output: PathBuf,
+}
+
+impl View {
+    /// Views for `cameras`, in order. A lone camera renders to `output`, and
+    /// with several each one's name is added to the file stem, so camera2 of
+    /// `scene1.png` renders to `scene1-camera2.png`. With no cameras there is
+    /// a single view from the default camera.
+    pub fn from_cameras(cameras: Vec<(String, Camera)>, output: &Path) -> Vec<View> {
+        if cameras.is_empty() {
+            return vec![View {
+                name: "camera1".to_string(),
+                camera: Camera::default(),
+                output: output.to_path_buf(),
+            }];
+        }
+        let several = cameras.len() > 1;
+        cameras
+            .into_iter()
+            .map(|(name, camera)| {
+                let output = if several {
+                    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+                    let mut file = format!("{}-{}", stem, name);
+                    if let Some(extension) = output.extension() {
+                        file = format!("{}.{}", file, extension.to_string_lossy());
+                    }
+                    output.with_file_name(file)
+                } else {
+                    output.to_path_buf()
+                };
+                View {
+                    name,
+                    camera,
+                    output,
+                }
+            })
+            .collect()
+    }
+}
 
 #[derive(Debug)]
 pub struct World {
-    pub camera: Camera,
-    pub bvh_node: Node,
+    pub views: Vec<View>,
+    pub bvh: Bvh,
     pub objects: Objects,
     pub lights: Vec<Light>,
-    pub ambient: Color,
+    /// Seen by rays that leave the scene, and sampled as one of `lights`.
+    pub environment: Option<Arc<Environment>>,
     pub attenuation: [f32; 3],
+    /// Bounces before rays end, or no limit when negative.
     pub max_depth: i32,
+    pub shading: ShadingModel,
+    pub samples: u32,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub seed: u64,
+    pub display: DisplayTransform,
+    /// Whether rendering reports the scanlines left on stderr.
+    pub progress: bool,
+}
+
+impl World {
+    /// Index of the view called `name`.
+    pub fn view(&self, name: &str) -> Option<usize> {
+        self.views.iter().position(|v| v.name == name)
+    }
+
+    /// Replaces the BVH with one built by `builder`.
+    pub fn build_bvh(&mut self, builder: Builder) {
+        self.bvh = Bvh::new(&self.objects, builder);
+    }
+
+    /// Focuses every camera with `autofocus` set on the first surface hit
+    /// at the centre of its image, if there is one.
+    pub fn autofocus(&mut self) {
+        for i in 0..self.views.len() {
+            let camera = self.views[i].camera;
+            if !camera.autofocus {
+                continue;
+            }
+            let ray = camera.sample_ray(camera.height / 2.0, camera.width / 2.0);
+            if let Some(rec) = ray.and_then(|r| self.hit(&r, 0.001, f32::MAX)) {
+                self.views[i].camera.focus_distance = dot(rec.point - camera.look_from, -camera.w);
+            }
+        }
+    }
+
+    /// Radiance reaching a ray that leaves the scene along `direction`.
+    pub fn background(&self, direction: Vec3) -> Color {
+        let mut color = match &self.environment {
+            Some(environment) => environment.radiance(direction),
+            None => BLACK,
+        };
+        for light in &self.lights {
+            if let Light::Sun {
+                direction: sun,
+                radius,
+                radiance,
+                ..
+            } = light
+            {
+                if dot(direction.normalize(), *sun) >= degrees_to_radians(*radius).cos() {
+                    color += *radiance;
+                }
+            }
+        }
+        color
+    }
+
+    /// Closest intersection of `ray` with the scene, found through the BVH.
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        self.bvh.hit(&self.objects, ray, t_min, t_max)
+    }
+
+    /// Whether anything blocks `ray` between `t_min` and `t_max`. Stops at the
+    /// first intersection found, so use this for shadow rays.
+    pub fn occluded(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.bvh.any_hit(&self.objects, ray, t_min, t_max)
+    }
 }
@@ -1,2 +1,3 @@
+pub mod disc;
 pub mod sphere;
 pub mod triangle;
\ No newline at end of file
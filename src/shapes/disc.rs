@@ -0,0 +1,53 @@
+use crate::aabb::Aabb;
+use crate::geom::{dot, Point3, Ray, Vec3};
+use crate::material::Material;
+use crate::object::Hit;
+use std::sync::Arc;
+
+/// Flat disc facing along `normal`, given in world space.
+#[derive(Debug, Clone)]
+pub struct Disc {
+    pub center: Point3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material: Arc<Material>,
+    pub bounding_box: Aabb,
+}
+
+impl Disc {
+    pub fn new(center: Point3, normal: Vec3, radius: f32, material: Arc<Material>) -> Self {
+        let normal = normal.normalize();
+        Self {
+            center,
+            normal,
+            radius,
+            material,
+            bounding_box: Self::bounding_box(center, normal, radius),
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        let denom = dot(self.normal, r.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = dot(self.center - r.origin, self.normal) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let p = r.at(t);
+        if (p - self.center).length_squared() > self.radius * self.radius {
+            return None;
+        }
+        Some(Hit::new(p, t, self.normal, self.material.clone()))
+    }
+
+    /// The disc reaches `radius * sqrt(1 - n_i^2)` from its centre along
+    /// each axis `i`.
+    pub fn bounding_box(center: Point3, normal: Vec3, radius: f32) -> Aabb {
+        let e = |n: f32| radius * (1.0 - n * n).max(0.0).sqrt();
+        let extent = Vec3::new(e(normal.x), e(normal.y), e(normal.z));
+        // Axis aligned discs have a flat box, which the slab test rejects.
+        Aabb::new(center - extent, center + extent).pad(1e-4)
+    }
+}
@@ -23,7 +23,7 @@ impl Sphere {
             material,
             transform,
             inv_transform,
-            bounding_box: Self::bounding_box(radius),
+            bounding_box: Self::bounding_box(center, radius, transform),
         }
     }
 
@@ -60,11 +60,19 @@ impl Sphere {
         ))
     }
 
-    pub fn bounding_box(radius: f32) -> Aabb {
-        Aabb::new(
-            vec3(-radius, -radius, -radius),
-            vec3(radius, radius, radius),
-        )
+    /// World-space box of the transformed sphere. Each row of the linear part
+    /// of `transform` stretches the radius along that world axis, which gives
+    /// the tight box of the resulting ellipsoid.
+    pub fn bounding_box(center: Point3, radius: f32, transform: Mat4) -> Aabb {
+        let c = transform.transform_point3(center);
+        let m = transform.transpose();
+        let extent = radius
+            * vec3(
+                m.x_axis.truncate().length(),
+                m.y_axis.truncate().length(),
+                m.z_axis.truncate().length(),
+            );
+        Aabb::new(c - extent, c + extent)
     }
 }
 
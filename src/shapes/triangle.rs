@@ -31,7 +31,7 @@ impl Triangle {
             material,
             transform,
             inv_transform,
-            bounding_box: Self::bounding_box(vertex1, vertex2, vertex3),
+            bounding_box: Self::bounding_box(vertex1, vertex2, vertex3, transform),
         }
     }
 
@@ -68,13 +68,17 @@ impl Triangle {
         }
     }
 
-    pub fn bounding_box(v1: Point3, v2: Point3, v3: Point3) -> Aabb {
+    pub fn bounding_box(v1: Point3, v2: Point3, v3: Point3, transform: Mat4) -> Aabb {
+        let v1 = transform.transform_point3(v1);
+        let v2 = transform.transform_point3(v2);
+        let v3 = transform.transform_point3(v3);
         let x_min = v1.x.min(v2.x).min(v3.x);
         let x_max = v1.x.max(v2.x).max(v3.x);
         let y_min = v1.y.min(v2.y).min(v3.y);
         let y_max = v1.y.max(v2.y).max(v3.y);
         let z_min = v1.z.min(v2.z).min(v3.z);
         let z_max = v1.z.max(v2.z).max(v3.z);
-        Aabb::new(vec3(x_min, y_min, z_min), vec3(x_max, y_max, z_max))
+        // Axis aligned triangles have a flat box, which the slab test rejects.
+        Aabb::new(vec3(x_min, y_min, z_min), vec3(x_max, y_max, z_max)).pad(1e-4)
     }
 }
@@ -0,0 +1,208 @@
+use crate::environment::Environment;
+use crate::geom::{degrees_to_radians, dot, vec3, Color, Vec3, BLACK, PI};
+use crate::light::Light;
+
+/// Preetham, Shirley and Smits' analytic daylight model, with +y up like
+/// `Environment`. Radiance is in kcd/m² times `intensity`, so an intensity
+/// of 1/32 shows a white surface under a high sun at about 1. Below the
+/// horizon is the ground, a Lambertian plane of colour `ground_albedo` lit
+/// by the sun and sky.
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    /// Unit direction towards the sun.
+    pub sun_direction: Vec3,
+    /// Haze, from 2 for a clear sky to about 10 for a hazy one.
+    pub turbidity: f32,
+    pub ground_albedo: Color,
+    pub intensity: f32,
+    zenith: [f32; 3],
+    coefficients: [[f32; 5]; 3],
+    ground: Color,
+}
+
+/// Illuminance of the sun above the atmosphere in klx.
+const SOLAR_ILLUMINANCE: f32 = 128.0;
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f32, ground_albedo: Color, intensity: f32) -> Self {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity;
+        // The model does not cover the sun below the horizon.
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos().min(PI / 2.0 - 1e-3);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let powers = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let poly = |a: [f32; 4], b: [f32; 4], c: [f32; 4]| {
+            (0..4)
+                .map(|i| (t * t * a[i] + t * b[i] + c[i]) * powers[i])
+                .sum::<f32>()
+        };
+        let x = poly(
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        );
+        let y = poly(
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        );
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let mut sky = Self {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            intensity,
+            zenith: [luminance, x, y],
+            coefficients,
+            ground: BLACK,
+        };
+        sky.ground = ground_albedo * sky.ground_irradiance() / PI;
+        sky
+    }
+
+    /// Radiance of the sky or ground seen along `direction`, without the
+    /// sun itself.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let d = direction.normalize();
+        let c = if d.y < 0.0 { self.ground } else { self.sky(d) };
+        self.intensity * c
+    }
+
+    /// Radiance of the sun seen through the atmosphere, for a disc of
+    /// `radius` degrees.
+    pub fn sun_radiance(&self, radius: f32) -> Color {
+        let solid_angle = 2.0 * PI * (1.0 - degrees_to_radians(radius).cos());
+        self.intensity * self.sun_irradiance() / solid_angle
+    }
+
+    /// The sun as a light of `radius` degrees taking `samples` shadow rays.
+    pub fn sun(&self, radius: f32, samples: u32) -> Light {
+        Light::Sun {
+            direction: self.sun_direction,
+            radius,
+            radiance: self.sun_radiance(radius),
+            samples,
+        }
+    }
+
+    /// The sky and ground as an environment map of the given size.
+    pub fn environment(&self, width: u32, height: u32) -> Environment {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for j in 0..height {
+            let theta = PI * (j as f32 + 0.5) / height as f32;
+            for i in 0..width {
+                let phi = 2.0 * PI * ((i as f32 + 0.5) / width as f32 - 0.5);
+                let d = vec3(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                pixels.push(self.radiance(d));
+            }
+        }
+        Environment::new(pixels, width, height, 1.0, 0.0)
+    }
+
+    /// Sky radiance in kcd/m² above the horizon, from the luminance and
+    /// chromaticity of the zenith scaled by the model's distribution.
+    fn sky(&self, d: Vec3) -> Color {
+        // Rays grazing the horizon would make the distribution blow up.
+        let cos_theta = d.y.max(0.01);
+        let cos_gamma = dot(d, self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let cos_theta_s = self.sun_direction.y.max(1e-3);
+        let theta_s = cos_theta_s.acos();
+        let f = |[a, b, c, d, e]: [f32; 5], cos_theta: f32, gamma: f32, cos_gamma: f32| {
+            (1.0 + a * (b / cos_theta).exp())
+                * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+        };
+        let [luminance, x, y] = [0, 1, 2].map(|k| {
+            let coefficients = self.coefficients[k];
+            self.zenith[k] * f(coefficients, cos_theta, gamma, cos_gamma)
+                / f(coefficients, 1.0, theta_s, cos_theta_s)
+        });
+        xyy_to_rgb(x, y, luminance.max(0.0))
+    }
+
+    /// Sunlight on a surface facing the sun, in klx, after Rayleigh and
+    /// aerosol extinction at red, green and blue wavelengths.
+    fn sun_irradiance(&self) -> Color {
+        if self.sun_direction.y <= 0.0 {
+            return BLACK;
+        }
+        let theta_s = self.sun_direction.y.acos();
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f32| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        };
+        SOLAR_ILLUMINANCE
+            * Color::new(
+                transmittance(0.65),
+                transmittance(0.57),
+                transmittance(0.475),
+            )
+    }
+
+    /// Light reaching the ground from the sun and the sky above it.
+    fn ground_irradiance(&self) -> Color {
+        const STEPS: usize = 32;
+        let mut sky = BLACK;
+        for j in 0..STEPS {
+            let theta = 0.5 * PI * (j as f32 + 0.5) / STEPS as f32;
+            for i in 0..2 * STEPS {
+                let phi = PI * (i as f32 + 0.5) / STEPS as f32;
+                let d = vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                // Radiance times cos and the patch's solid angle.
+                sky += self.sky(d) * theta.cos() * theta.sin();
+            }
+        }
+        let patch = (0.5 * PI / STEPS as f32) * (PI / STEPS as f32);
+        sky * patch + self.sun_irradiance() * self.sun_direction.y.max(0.0)
+    }
+}
+
+/// Linear sRGB of a CIE xyY colour.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Color {
+    if y <= 0.0 {
+        return BLACK;
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+    .max(BLACK)
+}
@@ -0,0 +1,206 @@
+//! Renders the test scenes and compares them with the reference images that
+//! ship next to them in testscenes. A render fails when its RMSE, PSNR or
+//! SSIM against the reference is outside the scene's tolerance, and then its
+//! image and a heatmap of the per-pixel error are written to
+//! `target/tmp/golden` for inspection. The test profile is optimized so that
+//! these renders take seconds.
+
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+use ucsd168::integrators::gl::GlIntegrator;
+use ucsd168::parse::parse_scene;
+use ucsd168::render::render_view;
+
+/// Limits on how far a render may drift from its reference.
+#[derive(Debug, Clone, Copy)]
+struct Tolerance {
+    max_rmse: f64,
+    min_psnr: f64,
+    min_ssim: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Metrics {
+    rmse: f64,
+    psnr: f64,
+    ssim: f64,
+}
+
+/// `path` relative to the crate.
+fn resolve(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn render(scene: &str, camera: &str) -> RgbImage {
+    let world = parse_scene(resolve(scene)).unwrap();
+    let view = world.view(camera).unwrap();
+    let film = render_view(&world, view, &mut GlIntegrator);
+    RgbImage::from_raw(film.width, film.height, world.display.to_rgb8(&film)).unwrap()
+}
+
+fn golden(scene: &str, camera: &str, reference: &str, tolerance: Tolerance) {
+    let actual = render(scene, camera);
+    let expected = image::open(resolve(reference)).unwrap().to_rgb8();
+    check(scene, &actual, reference, &expected, tolerance);
+}
+
+fn check(
+    scene: &str,
+    actual: &RgbImage,
+    reference: &str,
+    expected: &RgbImage,
+    tolerance: Tolerance,
+) {
+    assert_eq!(
+        actual.dimensions(),
+        expected.dimensions(),
+        "{} renders at a different size than {}",
+        scene,
+        reference
+    );
+
+    let metrics = compare(actual, expected);
+    println!("{}: {:?}", scene, metrics);
+    let pass = metrics.rmse <= tolerance.max_rmse
+        && metrics.psnr >= tolerance.min_psnr
+        && metrics.ssim >= tolerance.min_ssim;
+    if !pass {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&dir).unwrap();
+        let stem = Path::new(reference).file_stem().unwrap().to_string_lossy();
+        actual.save(dir.join(format!("{}.png", stem))).unwrap();
+        heatmap(actual, expected)
+            .save(dir.join(format!("{}-diff.png", stem)))
+            .unwrap();
+        panic!(
+            "{} differs from {}: {:?}, tolerance {:?}, images in {}",
+            scene,
+            reference,
+            metrics,
+            tolerance,
+            dir.display()
+        );
+    }
+}
+
+fn compare(actual: &RgbImage, expected: &RgbImage) -> Metrics {
+    let mut sum = 0.0;
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        for c in 0..3 {
+            let d = (a[c] as f64 - e[c] as f64) / 255.0;
+            sum += d * d;
+        }
+    }
+    let rmse = (sum / actual.len() as f64).sqrt();
+    let psnr = if rmse == 0.0 {
+        f64::INFINITY
+    } else {
+        -20.0 * rmse.log10()
+    };
+    Metrics {
+        rmse,
+        psnr,
+        ssim: ssim(&luma(actual), &luma(expected), actual.width() as usize),
+    }
+}
+
+fn luma(image: &RgbImage) -> Vec<f64> {
+    image
+        .pixels()
+        .map(|p| (0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64) / 255.0)
+        .collect()
+}
+
+/// Mean structural similarity over 8x8 windows spaced 4 pixels apart.
+fn ssim(a: &[f64], b: &[f64], width: usize) -> f64 {
+    const WINDOW: usize = 8;
+    const STRIDE: usize = 4;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+    let height = a.len() / width;
+    let n = (WINDOW * WINDOW) as f64;
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in (0..=height - WINDOW).step_by(STRIDE) {
+        for x0 in (0..=width - WINDOW).step_by(STRIDE) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y0 + WINDOW {
+                for x in x0..x0 + WINDOW {
+                    let (va, vb) = (a[y * width + x], b[y * width + x]);
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let var_a = saa / n - ma * ma;
+            let var_b = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// Largest per-channel error of each pixel, from black through red to
+/// yellow at full scale.
+fn heatmap(actual: &RgbImage, expected: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, e) = (actual.get_pixel(x, y), expected.get_pixel(x, y));
+        let d = (0..3).map(|c| a[c].abs_diff(e[c])).max().unwrap() as u32;
+        let red = (2 * d).min(255) as u8;
+        let green = (2 * d).saturating_sub(255).min(255) as u8;
+        Rgb([red, green, 0])
+    })
+}
+
+macro_rules! golden {
+    (
+        $name:ident: $scene:literal vs $reference:literal,
+        rmse $rmse:literal, psnr $psnr:literal, ssim $ssim:literal
+    ) => {
+        golden!(
+            $name: $scene camera "camera1" vs $reference,
+            rmse $rmse, psnr $psnr, ssim $ssim
+        );
+    };
+    (
+        $name:ident: $scene:literal camera $camera:literal vs $reference:literal,
+        rmse $rmse:literal, psnr $psnr:literal, ssim $ssim:literal
+    ) => {
+        #[test]
+        fn $name() {
+            golden(
+                $scene,
+                $camera,
+                $reference,
+                Tolerance {
+                    max_rmse: $rmse,
+                    min_psnr: $psnr,
+                    min_ssim: $ssim,
+                },
+            );
+        }
+    };
+}
+
+// Tolerances sit just outside the current renders, so any change to the
+// shading shows up. The jpg references leave more noise.
+golden!(scene2_camera1: "tests/scenes/scene2-cameras.test" camera "camera1" vs "testscenes/scene2-camera1.jpg", rmse 0.019, psnr 34.5, ssim 0.986);
+golden!(scene2_camera2: "tests/scenes/scene2-cameras.test" camera "camera2" vs "testscenes/scene2-camera2.jpg", rmse 0.019, psnr 34.5, ssim 0.988);
+golden!(scene2_camera3: "tests/scenes/scene2-cameras.test" camera "camera3" vs "testscenes/scene2-camera3.jpg", rmse 0.021, psnr 33.6, ssim 0.985);
+golden!(scene3: "testscenes/scene3.test" vs "testscenes/scene3.jpg", rmse 0.026, psnr 31.6, ssim 0.982);
+golden!(scene4_ambient: "testscenes/scene4-ambient.test" vs "testscenes/scene4-ambient.png", rmse 0.0036, psnr 49.0, ssim 0.9997);
+golden!(scene4_emission: "testscenes/scene4-emission.test" vs "testscenes/scene4-emission.png", rmse 0.0036, psnr 49.0, ssim 0.9997);
+golden!(scene4_specular: "testscenes/scene4-specular.test" vs "testscenes/scene4-specular.png", rmse 0.0009, psnr 61.0, ssim 0.9997);
+golden!(scene5: "testscenes/scene5.test" vs "testscenes/scene5.png", rmse 0.0070, psnr 43.2, ssim 0.998);
+
+// The remaining references are of different versions of their scenes, so
+// they are not compared: scene1-camera1.jpg shows a single red quad where
+// scene1.test has two yellow ones, scene4-diffuse.png has neither the mirror
+// finish nor the legs of scene4-diffuse.test, and scene6.png has a green wall
+// and a bronze egg where scene6.test has blue ones.